@@ -0,0 +1,44 @@
+//! Colors for terminal output, only with the color feature
+//! Without the feature, or once disabled, text is passed through unchanged
+
+#[cfg(feature = "color")]
+use colored::Colorize;
+
+#[cfg(feature = "color")]
+pub fn set_enabled(enabled: bool) {
+    //! Turn colors on or off for all following output
+    colored::control::set_override(enabled);
+}
+
+#[cfg(not(feature = "color"))]
+pub fn set_enabled(_enabled: bool) {}
+
+#[cfg(feature = "color")]
+pub fn red(text: &str) -> String {
+    text.red().to_string()
+}
+
+#[cfg(not(feature = "color"))]
+pub fn red(text: &str) -> String {
+    text.to_string()
+}
+
+#[cfg(feature = "color")]
+pub fn green(text: &str) -> String {
+    text.green().to_string()
+}
+
+#[cfg(not(feature = "color"))]
+pub fn green(text: &str) -> String {
+    text.to_string()
+}
+
+#[cfg(feature = "color")]
+pub fn bold(text: &str) -> String {
+    text.bold().to_string()
+}
+
+#[cfg(not(feature = "color"))]
+pub fn bold(text: &str) -> String {
+    text.to_string()
+}
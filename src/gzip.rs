@@ -0,0 +1,49 @@
+//! Compression of data files ending in .gz, only with the gzip feature
+//! Compressed files cannot be appended to, so they are always rewritten as a whole
+
+use std::fs::File;
+use std::io::Read;
+#[cfg(feature = "gzip")]
+use std::io::Write;
+
+pub fn is_compressed(file_name: &str) -> bool {
+    //! Check whether a data file is compressed, by its extension
+    file_name.ends_with(".gz")
+}
+
+#[cfg(feature = "gzip")]
+pub fn reader(file: File) -> std::io::Result<Box<dyn Read>> {
+    //! Decompress the file while it is read
+    //! An empty file (like one just created to be locked) holds nothing
+    if file.metadata()?.len() == 0 {
+        return Ok(Box::new(std::io::empty()));
+    }
+    Ok(Box::new(flate2::read::MultiGzDecoder::new(file)))
+}
+
+#[cfg(not(feature = "gzip"))]
+pub fn reader(_file: File) -> std::io::Result<Box<dyn Read>> {
+    Err(unsupported())
+}
+
+#[cfg(feature = "gzip")]
+pub fn write(file: &mut File, contents: &[u8]) -> std::io::Result<()> {
+    //! Write the compressed contents to the file
+    let mut encoder = flate2::write::GzEncoder::new(file, flate2::Compression::default());
+    encoder.write_all(contents)?;
+    encoder.finish()?;
+    Ok(())
+}
+
+#[cfg(not(feature = "gzip"))]
+pub fn write(_file: &mut File, _contents: &[u8]) -> std::io::Result<()> {
+    Err(unsupported())
+}
+
+#[cfg(not(feature = "gzip"))]
+pub fn unsupported() -> std::io::Error {
+    std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "compressed data files are not available, build fima with --features gzip",
+    )
+}
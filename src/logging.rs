@@ -0,0 +1,44 @@
+//! Diagnostic messages on stderr, off unless asked for with -v
+//! -v logs reading and writing of files and lines that are skipped,
+//! -vv also every purchase read
+
+use log::{Level, LevelFilter, Log, Metadata, Record};
+
+struct StderrLogger;
+
+impl Log for StderrLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if self.enabled(record.metadata()) {
+            let level: &str = match record.level() {
+                Level::Error => "error",
+                Level::Warn => "warning",
+                Level::Info => "info",
+                Level::Debug => "debug",
+                Level::Trace => "trace",
+            };
+            eprintln!("[{}] {}", level, record.args());
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+static LOGGER: StderrLogger = StderrLogger;
+
+pub fn init(verbosity: usize) {
+    //! Log messages up to the level of the verbosity, the number of times -v was given
+    let level: LevelFilter = match verbosity {
+        0 => LevelFilter::Off,
+        1 => LevelFilter::Info,
+        2 => LevelFilter::Debug,
+        _ => LevelFilter::Trace,
+    };
+    // only fails if a logger was set before, which then stays in place
+    if log::set_logger(&LOGGER).is_ok() {
+        log::set_max_level(level);
+    }
+}
@@ -1,9 +1,26 @@
-use chrono::{NaiveDate};
-use std::fs::File;
-use std::io::{Read, Write};
-use std::str::Split;
+mod color;
+mod gzip;
+mod logging;
+mod rounding;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+mod stats;
+#[cfg(feature = "tui")]
+mod tui;
 
-#[derive(Debug)]
+use chrono::{Datelike, Local, Months, NaiveDate};
+use log::{debug, info};
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::hash::{Hash, Hasher};
+use std::io::{BufRead, BufReader, IsTerminal, Read, Seek, SeekFrom, Write};
+use std::sync::{Mutex, OnceLock};
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(from = "String", into = "String")]
 enum ProductType {
     Food,
     Culture,
@@ -13,363 +30,7349 @@ enum ProductType {
     Presents,
     Style,
     Other,
+    Custom(String),
+}
+
+// common synonyms of the built-in categories
+const ALIASES: &[(&str, &str)] = &[
+    ("tech", "technology"),
+    ("gadgets", "technology"),
+    ("electronics", "technology"),
+    ("groceries", "food"),
+    ("grocery", "food"),
+    ("supermarket", "food"),
+    ("restaurant", "food"),
+    ("restaurants", "food"),
+    ("dining", "food"),
+    ("movies", "culture"),
+    ("cinema", "culture"),
+    ("music", "culture"),
+    ("courses", "education"),
+    ("tuition", "education"),
+    ("transport", "travel"),
+    ("flights", "travel"),
+    ("hotel", "travel"),
+    ("gifts", "presents"),
+    ("gift", "presents"),
+    ("clothes", "style"),
+    ("clothing", "style"),
+    ("fashion", "style"),
+];
+
+// aliases from the aliases file, taking precedence over the built-in ones
+// (set once at startup, before any purchases are read)
+static USER_ALIASES: OnceLock<HashMap<String, ProductType>> = OnceLock::new();
+
+// exchange rates from the rates file, in millionths of the displayed currency per unit
+// of another one (set once at startup, and only for commands that do not change purchases,
+// so prices are converted for reporting but stored as entered)
+static EXCHANGE_RATES: OnceLock<HashMap<String, i64>> = OnceLock::new();
+
+// warnings printed while reading purchases, kept so a cached report can repeat them
+static WARNINGS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+fn warn(message: String) {
+    //! Print a warning about the purchases read and record it in WARNINGS
+    eprintln!("Warning: {}", message);
+    WARNINGS.lock().unwrap().push(message);
 }
 
+// purchases dated in the future when they were read, see future_purchases; kept like WARNINGS,
+// but only repeated while they are still in the future
+static FUTURE_PURCHASES: Mutex<Vec<(String, NaiveDate)>> = Mutex::new(Vec::new());
+
+// strftime-like format of dates entered and shown, from --date-format (set once at startup)
+// the data file always keeps yyyy-mm-dd
+static DATE_FORMAT: OnceLock<String> = OnceLock::new();
+
 impl ProductType {
+    fn all_variants() -> [ProductType; 8] {
+        //! The built-in categories, i.e. every variant but Custom
+        [
+            ProductType::Food,
+            ProductType::Culture,
+            ProductType::Technology,
+            ProductType::Education,
+            ProductType::Travel,
+            ProductType::Presents,
+            ProductType::Style,
+            ProductType::Other,
+        ]
+    }
+
+    fn built_in_name(&self) -> Option<&'static str> {
+        //! Name of a built-in category as produced by Display, None for custom ones
+        match self {
+            ProductType::Food => Some("food"),
+            ProductType::Culture => Some("culture"),
+            ProductType::Technology => Some("technology"),
+            ProductType::Education => Some("education"),
+            ProductType::Travel => Some("travel"),
+            ProductType::Presents => Some("presents"),
+            ProductType::Style => Some("style"),
+            ProductType::Other => Some("other"),
+            ProductType::Custom(_) => None,
+        }
+    }
+
     fn from_string(product_type: &str) -> ProductType {
         //! Convert a string to a ProductType
-        match product_type {
-            "food" => ProductType::Food,
-            "culture" => ProductType::Culture,
-            "technology" => ProductType::Technology,
-            "education" => ProductType::Education,
-            "travel" => ProductType::Travel,
-            "presents" => ProductType::Presents,
-            "style" => ProductType::Style,
-            _ => ProductType::Other,
+        //! Aliases like "groceries" are resolved to their category
+        //! Unknown names are kept as their own custom category
+        let product_type: String = product_type.trim().to_lowercase();
+        if let Some(category) = USER_ALIASES
+            .get()
+            .and_then(|aliases| aliases.get(&product_type))
+        {
+            return category.clone();
         }
+        let name: &str = ALIASES
+            .iter()
+            .find(|(alias, _)| *alias == product_type)
+            .map_or(product_type.as_str(), |(_, name)| name);
+        if name.is_empty() {
+            return ProductType::Other;
+        }
+        ProductType::all_variants()
+            .into_iter()
+            .find(|category| category.built_in_name() == Some(name))
+            .unwrap_or_else(|| ProductType::Custom(name.to_string()))
     }
+}
 
-    fn to_string(&self) -> String {
+impl fmt::Display for ProductType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         //! Convert a ProductType to a string
-        match self {
-            ProductType::Food => String::from("food"),
-            ProductType::Culture => String::from("culture"),
-            ProductType::Technology => String::from("technology"),
-            ProductType::Education => String::from("education"),
-            ProductType::Travel => String::from("travel"),
-            ProductType::Presents => String::from("presents"),
-            ProductType::Style => String::from("style"),
-            ProductType::Other => String::from("other"),
-        }
+        let name: &str = match self {
+            ProductType::Custom(name) => name,
+            built_in => built_in.built_in_name().unwrap_or_default(),
+        };
+        write!(f, "{}", name)
     }
 }
 
-#[derive(Debug)]
+impl From<String> for ProductType {
+    fn from(product_type: String) -> ProductType {
+        ProductType::from_string(&product_type)
+    }
+}
+
+impl From<ProductType> for String {
+    fn from(product_type: ProductType) -> String {
+        product_type.to_string()
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 struct Product {
     name: String,
-    price: f32,
+    price_cents: i64,
     product_type: ProductType,
     brand: String,
 }
 
+#[derive(Debug)]
+enum ValidationError {
+    NegativePrice(i64),
+    ZeroQuantity,
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ValidationError::NegativePrice(price_cents) => {
+                write!(
+                    f,
+                    "price {} must not be negative",
+                    format_cents(*price_cents)
+                )
+            }
+            ValidationError::ZeroQuantity => write!(f, "quantity must be more than 0"),
+        }
+    }
+}
+
+// Two products are equal iff their name (ignoring case), price and product type all match;
+// the brand is not taken into account, so a Product can be used as a HashMap key
+// for grouping the same item bought from different brands.
+impl PartialEq for Product {
+    fn eq(&self, other: &Product) -> bool {
+        self.key() == other.key()
+            && self.price_cents == other.price_cents
+            && self.product_type == other.product_type
+    }
+}
+
+impl Eq for Product {}
+
+impl Hash for Product {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        // must stay consistent with PartialEq: hash exactly the compared fields
+        self.key().hash(state);
+        self.price_cents.hash(state);
+        self.product_type.hash(state);
+    }
+}
+
+fn check_price(price_cents: i64) -> Result<i64, ValidationError> {
+    //! Check that a price (in cents) is not negative
+    if price_cents < 0 {
+        return Err(ValidationError::NegativePrice(price_cents));
+    }
+    Ok(price_cents)
+}
+
+fn check_quantity(quantity: Quantity) -> Result<Quantity, ValidationError> {
+    //! Check that a quantity is not zero
+    if quantity.milli == 0 {
+        return Err(ValidationError::ZeroQuantity);
+    }
+    Ok(quantity)
+}
+
 impl Product {
-    fn new(name: String, price: f32, product_type: ProductType, brand: String) -> Product {
+    fn new(
+        name: String,
+        price_cents: i64,
+        product_type: ProductType,
+        brand: String,
+    ) -> Result<Product, ValidationError> {
         //! Create a new product
-        //! by passing in a name, price and product_type
-        // parse name (remove spaces, convert to lowercase)
-        let name = name.trim().to_lowercase();
-        Product {
+        //! by passing in a name, price (in cents) and product_type
+        //! The price must not be negative
+        //! The name keeps its casing for display, see key
+        // parse name (remove spaces)
+        let name = name.trim().to_string();
+        Ok(Product {
             name,
-            price,
+            price_cents: check_price(price_cents)?,
             product_type,
             brand: brand.trim().to_lowercase(),
+        })
+    }
+
+    fn key(&self) -> String {
+        //! Lowercase name used for matching, so "iPhone" and "iphone" are the same product
+        self.name.to_lowercase()
+    }
+}
+
+// A quantity in thousandths, so goods sold by weight (1.5 kg) can be counted exactly;
+// whole quantities are written without decimals, so files from before stay unchanged.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, PartialOrd, Ord, Deserialize)]
+#[serde(try_from = "f64")]
+struct Quantity {
+    milli: u64,
+}
+
+impl Quantity {
+    fn parse(quantity: &str) -> Option<Quantity> {
+        //! Parse a decimal quantity like "1.5" or "3"
+        //! Digits beyond the third decimal place are rounded half up
+        let milli: i64 = parse_fixed(quantity, 3)?;
+        Some(Quantity {
+            milli: u64::try_from(milli).ok()?,
+        })
+    }
+
+    fn from_f64(quantity: f64) -> Option<Quantity> {
+        //! Convert a decimal number, rounded to thousandths
+        let milli: f64 = (quantity * 1000.0).round();
+        if !milli.is_finite() || milli < 0.0 || milli > u64::MAX as f64 {
+            return None;
+        }
+        Some(Quantity {
+            milli: milli as u64,
+        })
+    }
+
+    fn is_whole(self) -> bool {
+        self.milli.is_multiple_of(1000)
+    }
+
+    fn as_f64(self) -> f64 {
+        self.milli as f64 / 1000.0
+    }
+
+    fn value(self, price_cents: i64) -> i64 {
+        //! Price of this quantity at the given unit price, rounded half to even to whole cents
+        rounding::round_div(price_cents * self.milli as i64, 1000)
+    }
+}
+
+impl fmt::Display for Quantity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (whole, fraction) = (self.milli / 1000, self.milli % 1000);
+        if fraction == 0 {
+            return write!(f, "{}", whole);
         }
+        let fraction: String = format!("{:03}", fraction);
+        write!(f, "{}.{}", whole, fraction.trim_end_matches('0'))
     }
 }
 
-#[derive(Debug)]
+impl std::ops::Add for Quantity {
+    type Output = Quantity;
+
+    fn add(self, other: Quantity) -> Quantity {
+        Quantity {
+            milli: self.milli + other.milli,
+        }
+    }
+}
+
+impl TryFrom<f64> for Quantity {
+    type Error = String;
+
+    fn try_from(quantity: f64) -> Result<Quantity, String> {
+        Quantity::from_f64(quantity).ok_or_else(|| format!("invalid quantity {}", quantity))
+    }
+}
+
+// whole quantities are stored as integers, as they were before fractions were allowed
+impl Serialize for Quantity {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if self.is_whole() {
+            serializer.serialize_u64(self.milli / 1000)
+        } else {
+            serializer.serialize_f64(self.as_f64())
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 struct Purchase {
     product: Product,
-    quantity: u32,
+    quantity: Quantity,
     date: NaiveDate,
     shop: String,
+    #[serde(default)]
+    tags: Vec<String>,
+    // money coming back for a returned product, which counts against spending
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    refund: bool,
+    // why the purchase was made, free text
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    note: Option<String>,
+    // ISO code of the currency of the price, if it is not the one amounts are shown in;
+    // converted with the exchange rates only when reporting
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    currency: Option<String>,
+}
+
+impl Eq for Purchase {}
+
+// Purchases are ordered by date, then by value, so sorting lists them chronologically
+// with the cheaper of two purchases on the same day first.
+// Remaining ties are broken on every other field compared by PartialEq (the brand is ignored
+// by both, see Product), so two purchases are Equal exactly when they are ==.
+impl Ord for Purchase {
+    fn cmp(&self, other: &Purchase) -> Ordering {
+        self.date
+            .cmp(&other.date)
+            .then_with(|| self.value().cmp(&other.value()))
+            .then_with(|| self.product.key().cmp(&other.product.key()))
+            .then_with(|| self.product.price_cents.cmp(&other.product.price_cents))
+            .then_with(|| self.product.product_type.cmp(&other.product.product_type))
+            .then_with(|| self.quantity.cmp(&other.quantity))
+            .then_with(|| self.shop.cmp(&other.shop))
+            .then_with(|| self.tags.cmp(&other.tags))
+            .then_with(|| self.refund.cmp(&other.refund))
+            .then_with(|| self.note.cmp(&other.note))
+            .then_with(|| self.currency.cmp(&other.currency))
+    }
+}
+
+impl PartialOrd for Purchase {
+    fn partial_cmp(&self, other: &Purchase) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+fn normalize_tag(tag: &str) -> String {
+    //! Normalize a tag to lowercase, joining words with dashes
+    //! so tags can be stored as a space-separated group
+    tag.split_whitespace()
+        .collect::<Vec<&str>>()
+        .join("-")
+        .to_lowercase()
 }
 
 impl Purchase {
-    fn new(product: Product, quantity: u32, date: NaiveDate, shop: String) -> Purchase {
+    fn new(
+        product: Product,
+        quantity: Quantity,
+        date: NaiveDate,
+        shop: String,
+    ) -> Result<Purchase, ValidationError> {
         //! Create a new purchase
         //! by passing in a product, quantity and date
-        Purchase {
+        //! The quantity must not be zero
+        Ok(Purchase {
             product,
-            quantity,
+            quantity: check_quantity(quantity)?,
             date,
             shop: shop.trim().to_lowercase(),
+            tags: Vec::new(),
+            refund: false,
+            note: None,
+            currency: None,
+        })
+    }
+
+    fn with_note(self, note: &str) -> Purchase {
+        //! Attach a note to the purchase (trimmed, an empty note is none)
+        let note: &str = note.trim();
+        Purchase {
+            note: (!note.is_empty()).then(|| note.to_string()),
+            ..self
+        }
+    }
+
+    fn with_currency(self, currency: Option<String>) -> Purchase {
+        //! Set the currency the price is in, None for the one amounts are shown in
+        Purchase { currency, ..self }
+    }
+
+    fn price_field(&self) -> String {
+        //! The price as written in the data file, followed by its currency code if it has one
+        match &self.currency {
+            Some(code) => format!("{} {}", format_cents(self.product.price_cents), code),
+            None => format_cents(self.product.price_cents),
+        }
+    }
+
+    fn with_refund(self, refund: bool) -> Purchase {
+        //! Mark the purchase as a refund (or not)
+        Purchase { refund, ..self }
+    }
+
+    fn signed_quantity(&self) -> String {
+        //! The quantity as written to files, negative for refunds
+        if self.refund {
+            format!("-{}", self.quantity)
+        } else {
+            self.quantity.to_string()
+        }
+    }
+
+    fn with_tags(self, tags: &[&str]) -> Purchase {
+        //! Attach tags to the purchase (normalized, empty ones are dropped)
+        Purchase {
+            tags: tags
+                .iter()
+                .map(|tag| normalize_tag(tag))
+                .filter(|tag| !tag.is_empty())
+                .collect(),
+            ..self
         }
     }
 
-    fn value(&self) -> f32 {
-        //! Calculate the value of a purchase
+    fn value(&self) -> i64 {
+        //! Calculate the value of a purchase in cents
         //! by multiplying the product price by the quantity
-        self.product.price * self.quantity as f32
+        //! Fractional quantities are rounded to whole cents, see Quantity::value
+        //! Refunds have a negative value
+        let value: i64 = self.quantity.value(self.product.price_cents);
+        if self.refund {
+            -value
+        } else {
+            value
+        }
     }
 }
 
-fn write_to_file(purchase: &Purchase, file_name: &str) {
-    //! Write purchase to file
-    //! by appending to the file
+fn parse_cents(amount: &str) -> Option<i64> {
+    //! Parse a decimal amount like "12.34" into cents (1234)
+    //! Digits beyond the second decimal place are rounded half up
+    parse_fixed(amount, 2)
+}
 
-    // open file if it exists, otherwise create it
-    let mut file = File::open(file_name).unwrap_or_else(|_| File::create(file_name).unwrap());
+fn parse_price(price: &str) -> Option<i64> {
+    //! Parse a price as written on receipts and bank statements, like "$1,299.00",
+    //! "1 299,00 €" or "99", into cents
+    //! A currency symbol in front or behind and thousands separators (commas, dots or
+    //! spaces) are dropped; the decimal separator is the last comma or dot, unless
+    //! another one of its kind comes before it, or it is the only separator and followed
+    //! by exactly three digits: both "1,299" and "1.299" are 1299
+    let price: &str =
+        price.trim_matches(|c: char| !(c.is_ascii_digit() || c == '-' || c == '.' || c == ','));
+    let price: String = price.chars().filter(|c| !c.is_whitespace()).collect();
+    let decimal: Option<usize> = price.rfind([',', '.']).filter(|&index| {
+        let separator: char = price[index..].chars().next().unwrap();
+        let digits: usize = price.len() - index - 1;
+        // "1,299" and "1.299.000" only group thousands
+        if price[..index].contains(separator) {
+            return false;
+        }
+        digits != 3 || price[..index].contains([',', '.'])
+    });
+    let normalized: String = price
+        .char_indices()
+        .filter_map(|(index, c)| match c {
+            ',' | '.' if Some(index) == decimal => Some('.'),
+            ',' | '.' => None,
+            c => Some(c),
+        })
+        .collect();
+    parse_cents(&normalized)
+}
 
-    // write purchase to file if file is empty
-    if file.metadata().unwrap().len() == 0 {
-        let purchase_string: String = format!(
-            "{}, {}, {}, {}, {}, {}, {}",
-            purchase.product.name,
-            purchase.product.price,
-            purchase.product.product_type.to_string(),
-            purchase.product.brand,
-            purchase.quantity,
-            purchase.date,
-            purchase.shop
-        );
-        file.write_all(purchase_string.as_bytes()).unwrap();
+fn split_currency_code(price: &str) -> (&str, Option<String>) {
+    //! Split a three letter currency code like "EUR" off the end of a price like "12.50 EUR"
+    let price: &str = price.trim();
+    match price.rsplit_once(char::is_whitespace) {
+        Some((amount, code))
+            if code.len() == 3 && code.chars().all(|c| c.is_ascii_alphabetic()) =>
+        {
+            (amount, Some(code.to_ascii_uppercase()))
+        }
+        _ => (price, None),
+    }
+}
+
+fn parse_fixed(amount: &str, decimals: u32) -> Option<i64> {
+    //! Parse a decimal number into an integer count of 10^-decimals units,
+    //! rounding half up on the first dropped digit
+    let amount = amount.trim();
+    let (negative, amount) = match amount.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, amount),
+    };
+    let (whole, fraction) = amount.split_once('.').unwrap_or((amount, ""));
+    if whole.is_empty() && fraction.is_empty() {
+        return None;
+    }
+    if !whole.chars().all(|c| c.is_ascii_digit()) || !fraction.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let whole: i64 = if whole.is_empty() {
+        0
     } else {
-        // add purchase to file if file is not empty
-        let mut contents: String = String::new();
-        file.read_to_string(&mut contents).unwrap();
-        let purchase_string = format!(
-            "\n{}, {}, {}, {}, {}, {}, {}",
-            purchase.product.name,
-            purchase.product.price,
-            purchase.product.product_type.to_string(),
-            purchase.product.brand,
-            purchase.quantity,
-            purchase.date,
-            purchase.shop
-        );
-        contents.push_str(&purchase_string);
-        let mut file: File = File::create(file_name).unwrap();
-        file.write_all(contents.as_bytes()).unwrap();
+        whole.parse().ok()?
+    };
+    let digits: Vec<i64> = fraction
+        .chars()
+        .map(|c| c.to_digit(10).unwrap() as i64)
+        .collect();
+    // amounts too large for an i64 are rejected instead of overflowing
+    let mut scaled: i64 = whole.checked_mul(10_i64.checked_pow(decimals)?)?;
+    for place in 0..decimals {
+        let digit: i64 = *digits.get(place as usize).unwrap_or(&0);
+        scaled = scaled.checked_add(digit * 10_i64.pow(decimals - 1 - place))?;
+    }
+    if digits.get(decimals as usize).unwrap_or(&0) >= &5 {
+        scaled = scaled.checked_add(1)?;
     }
+    Some(if negative { -scaled } else { scaled })
 }
 
-fn cli_update() {
-    //! Add a purchase from the command line
-    //! by asking the user for the product name, price, quantity and date
-    //! and writing the purchase to a file
+fn format_cents(cents: i64) -> String {
+    //! Format an amount in cents as a decimal string like "12.34"
+    let sign = if cents < 0 { "-" } else { "" };
+    format!("{}{}.{:02}", sign, cents.abs() / 100, cents.abs() % 100)
+}
+
+struct Currency {
+    symbol: String,
+    decimals: u32,
+    decimal_separator: char,
+    // separator between groups of three digits, none by default
+    grouping_separator: Option<char>,
+    // whether the symbol follows the amount (after a space) instead of preceding it
+    symbol_after: bool,
+}
+
+impl Default for Currency {
+    fn default() -> Currency {
+        Currency {
+            symbol: String::from("$"),
+            decimals: 2,
+            decimal_separator: '.',
+            grouping_separator: None,
+            symbol_after: false,
+        }
+    }
+}
+
+// display conventions of the locales FIMA_LOCALE can name:
+// (locale, symbol, decimal separator, grouping separator, symbol after the amount)
+const LOCALES: &[(&str, &str, char, char, bool)] = &[
+    ("en-US", "$", '.', ',', false),
+    ("en-GB", "£", '.', ',', false),
+    ("de-DE", "€", ',', '.', true),
+    ("es-ES", "€", ',', '.', true),
+    ("it-IT", "€", ',', '.', true),
+    ("fr-FR", "€", ',', '\u{202f}', true),
+];
+
+// ISO 4217 codes of common currency symbols
+const SYMBOL_CODES: &[(&str, &str)] = &[
+    ("$", "USD"),
+    ("US$", "USD"),
+    ("€", "EUR"),
+    ("£", "GBP"),
+    ("¥", "JPY"),
+    ("₹", "INR"),
+    ("₩", "KRW"),
+    ("Fr.", "CHF"),
+    ("zł", "PLN"),
+    ("kr", "SEK"),
+];
+
+impl Currency {
+    fn code(&self) -> Option<&str> {
+        //! ISO 4217 code of the currency, like "USD", if it is known from the symbol
+        //! A symbol of three capital letters (like --currency CHF) is taken as the code itself
+        if self.symbol.len() == 3 && self.symbol.bytes().all(|byte| byte.is_ascii_uppercase()) {
+            return Some(&self.symbol);
+        }
+        SYMBOL_CODES
+            .iter()
+            .find(|(symbol, _)| *symbol == self.symbol)
+            .map(|(_, code)| *code)
+    }
+
+    fn from_locale(locale: &str) -> Option<Currency> {
+        //! Display conventions of a locale like "de-DE", also written like "de_DE.UTF-8"
+        let locale: &str = locale.split('.').next().unwrap_or_default();
+        let locale: String = locale.replace('_', "-");
+        LOCALES
+            .iter()
+            .find(|(name, _, _, _, _)| name.eq_ignore_ascii_case(&locale))
+            .map(
+                |&(_, symbol, decimal_separator, grouping_separator, symbol_after)| Currency {
+                    symbol: symbol.to_string(),
+                    decimals: 2,
+                    decimal_separator,
+                    grouping_separator: Some(grouping_separator),
+                    symbol_after,
+                },
+            )
+    }
+}
+
+fn format_amount(cents: i64, currency: &Currency) -> String {
+    //! Format an amount in cents for display, like "$152.40", "€152,40" or "1.299,00 €"
+    //! Amounts are rounded half to even when fewer than two decimals are shown
+    let sign: &str = if cents < 0 { "-" } else { "" };
+    let cents: i64 = cents.abs();
+    let (whole, fraction): (i64, String) = match currency.decimals {
+        0 => (rounding::round_div(cents, 100), String::new()),
+        1 => {
+            let tenths: i64 = rounding::round_div(cents, 10);
+            (tenths / 10, (tenths % 10).to_string())
+        }
+        decimals => (
+            cents / 100,
+            format!("{:02}{}", cents % 100, "0".repeat(decimals as usize - 2)),
+        ),
+    };
+    let digits: String = whole.to_string();
+    let mut number: String = String::new();
+    for (index, digit) in digits.chars().enumerate() {
+        if let Some(separator) = currency.grouping_separator {
+            if index > 0 && (digits.len() - index).is_multiple_of(3) {
+                number.push(separator);
+            }
+        }
+        number.push(digit);
+    }
+    if !fraction.is_empty() {
+        number = format!("{}{}{}", number, currency.decimal_separator, fraction);
+    }
+    if currency.symbol_after {
+        format!("{}{} {}", sign, number, currency.symbol)
+    } else {
+        format!("{}{}{}", sign, currency.symbol, number)
+    }
+}
+
+fn quote_field(field: &str) -> String {
+    //! Quote a field of the data file if it would otherwise be misread
+    //! i.e. if it contains a comma or quote or has leading/trailing whitespace,
+    //! doubling any quotes inside it (as in CSV)
+    if field.contains([',', '"', '\n', '\r']) || field.trim() != field {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+fn split_fields(line: &str) -> Option<Vec<String>> {
+    //! Split a line of the data file into its fields
+    //! Fields are separated by commas; unquoted fields are trimmed
+    //! (so a stray \r of a Windows line ending is dropped),
+    //! quoted fields are kept verbatim with doubled quotes unescaped
+    //! Returns None if a quoted field is not terminated properly
+    let mut fields: Vec<String> = Vec::new();
+    let mut chars = line.chars().peekable();
     loop {
-        println!("Add a purchase");
-        // create product
-        println!("Enter product name:");
-        let mut name: String = String::new();
-        std::io::stdin().read_line(&mut name).unwrap();
-        name = name.trim().to_lowercase();
-        // check price is a float
-        let price = loop {
-            println!("Enter price:");
-            let mut price: String = String::new();
-            std::io::stdin().read_line(&mut price).unwrap();
-            match price.trim().parse::<f32>() {
-                Ok(price) => break price,
-                Err(_) => {
-                    println!("Price must be a float");
-                    continue;
+        // skip whitespace before the field
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        let mut field: String = String::new();
+        if chars.next_if_eq(&'"').is_some() {
+            loop {
+                match chars.next()? {
+                    '"' if chars.next_if_eq(&'"').is_some() => field.push('"'),
+                    '"' => break,
+                    c => field.push(c),
                 }
             }
-        };
-        // create product type
-        println!("Enter product type (food, culture, technology, education, travel, presents, style, other):");
-        let mut product_type: String = String::new();
-        std::io::stdin().read_line(&mut product_type).unwrap();
-        let product_type: ProductType = ProductType::from_string(&product_type.trim());
-        // brand
-        println!("Enter brand:");
-        let mut brand: String = String::new();
-        std::io::stdin().read_line(&mut brand).unwrap();
-        // check quantity is an integer
-        let quantity: u32 = loop {
-            println!("Enter quantity:");
-            let mut quantity: String = String::new();
-            std::io::stdin().read_line(&mut quantity).unwrap();
-            match quantity.trim().parse::<u32>() {
-                Ok(quantity) => break quantity,
-                Err(_) => {
-                    println!("Quantity must be an integer");
-                    continue;
-                }
+            // only whitespace may follow the closing quote
+            while chars.next_if(|c| c.is_whitespace()).is_some() {}
+            if chars.peek().is_some_and(|c| *c != ',') {
+                return None;
             }
-        };
-        // check date can be parsed
-        let date: NaiveDate = loop {
-            println!("Enter date (dd-mm-yyyy):");
-            let mut date: String = String::new();
-            std::io::stdin().read_line(&mut date).unwrap();
-            date = date.trim().parse().unwrap();  // parse date
-            match NaiveDate::parse_from_str(date.as_str(), "%d-%m-%Y") {
-                Ok(date) => break date,
-                Err(_) => {
-                    println!("Date must be in the format dd-mm-yyyy");
-                    continue;
-                }
+        } else {
+            while let Some(c) = chars.next_if(|c| *c != ',') {
+                field.push(c);
             }
-        };
-        // shop
-        println!("Enter shop:");
-        let mut shop: String = String::new();
-        std::io::stdin().read_line(&mut shop).unwrap();
-        let product = Product::new(name, price, product_type, brand);
-        let purchase = Purchase::new(product, quantity, date, shop);
-        write_to_file(&purchase, "purchase.txt");
-        println!("Purchase added");
-        println!("Add another purchase? (y/n)");
-        let mut answer = String::new();
-        std::io::stdin().read_line(&mut answer).unwrap();
-        if answer.trim() != "y" {
-            break;
+            field = field.trim_end().to_string();
+        }
+        fields.push(field);
+        if chars.next().is_none() {
+            return Some(fields);
         }
     }
 }
 
-fn read_from_file(file_name: &str) -> Vec<Purchase> {
-    //! Read purchases from file
-    //! by converting each line to a Purchase
-    //! and returning a vector of Purchase
-    let mut file: File = File::open(file_name).unwrap();
-    let mut contents: String = String::new();
-    file.read_to_string(&mut contents).unwrap();
-    let mut purchases: Vec<Purchase> = Vec::new();
-    for line in contents.lines() {
-        let mut fields: Split<&str> = line.split(", ");
-        // assert fields have correct types
-        if let (Some(name), Some(price), Some(product_type), Some(brand), Some(quantity), Some(date), Some(shop)) = (
-            fields.next(),
-            fields.next(),
-            fields.next(),
-            fields.next(),
-            fields.next(),
-            fields.next(),
-            fields.next(),
-        ) {
-            // create product and purchase
-            let product = Product {
-                name: String::from(name),
-                price: price.parse::<f32>().expect("price is not a float"),
-                product_type: ProductType::from_string(product_type),
-                brand: brand.to_string(),
-            };
-            let purchase = Purchase {
-                product,
-                quantity: quantity.parse::<u32>().expect("quantity is not an integer"),
-                date: date.parse::<NaiveDate>().expect("date cannot be parsed"),
-                shop: shop.to_string(),
-            };
-            // add purchase to purchases
-            purchases.push(purchase);
-        }
+fn purchase_to_line(purchase: &Purchase) -> String {
+    //! Convert a purchase to a line of the data file
+    //! Tags are stored space-separated in the next to last column, which is left empty without tags
+    //! The note is the last column, left out without a note
+    let note: String = match &purchase.note {
+        Some(note) => format!(", {}", quote_field(note)),
+        None => String::new(),
+    };
+    let line: String = format!(
+        "{}, {}, {}, {}, {}, {}, {}, {}{}",
+        quote_field(&purchase.product.name),
+        purchase.price_field(),
+        quote_field(&purchase.product.product_type.to_string()),
+        quote_field(&purchase.product.brand),
+        purchase.signed_quantity(),
+        purchase.date,
+        quote_field(&purchase.shop),
+        purchase.tags.join(" "),
+        note
+    );
+    line.trim_end().to_string()
+}
+
+fn write_purchase(
+    purchase: &Purchase,
+    writer: &mut impl Write,
+    first: bool,
+) -> std::io::Result<()> {
+    //! Write a single purchase as one line
+    //! preceded by a newline unless it is the first line of the output
+    if !first {
+        writer.write_all(b"\n")?;
     }
-    return purchases;
+    writer.write_all(purchase_to_line(purchase).as_bytes())
 }
 
-struct Bucket<'a> {
-    product_type: ProductType,
-    purchases: Vec<&'a Purchase>,
+fn write_purchases(purchases: &[Purchase], writer: &mut impl Write) -> std::io::Result<()> {
+    //! Write purchases one per line, without a trailing newline
+    for (index, purchase) in purchases.iter().enumerate() {
+        write_purchase(purchase, writer, index == 0)?;
+    }
+    Ok(())
 }
 
-impl<'a> Bucket<'a> {
-    fn new(product_type: ProductType, purchases: Vec<&Purchase>) -> Bucket {
-        //! Create a new bucket
-        //! by passing in a product_type and purchases
-        Bucket {
-            product_type,
-            purchases,
-        }
+fn write_to_file(purchase: &Purchase, file_name: &str) -> std::io::Result<u64> {
+    //! Write purchase to file
+    //! by appending a single line to the file
+    //! A compressed file is rewritten as a whole instead
+    //! Returns the length of the file in bytes before the purchase was appended,
+    //! or for a compressed file the number of purchases before it
+    if gzip::is_compressed(file_name) {
+        // keep other processes from writing in between
+        let _lock: File = lock_file(file_name)?;
+        let mut purchases: Vec<Purchase> = match read_from_file(file_name) {
+            Ok(purchases) => purchases,
+            Err(ReadError::IoError(error)) if error.kind() == std::io::ErrorKind::NotFound => {
+                Vec::new()
+            }
+            Err(error) => return Err(error.into()),
+        };
+        let len: u64 = purchases.len() as u64;
+        purchases.push(purchase.clone());
+        return write_all_to_file(&purchases, file_name).map(|()| len);
     }
+
+    // keep other processes from writing until the purchase is written,
+    // the lock is released when it is dropped
+    let _lock: File = lock_file(file_name)?;
+    append_line(file_name, &purchase_to_line(purchase))
 }
 
-fn sort_type_buckets(purchases: &Vec<Purchase>) -> Vec<Bucket> {
-    //! Sort purchases into buckets by product type
-    //! by iterating over purchases and adding each purchase to the correct bucket
-    //! and returning a vector of buckets
-    let mut food_bucket: Vec<&Purchase> = Vec::new();
-    let mut culture_bucket: Vec<&Purchase> = Vec::new();
-    let mut technology_bucket: Vec<&Purchase> = Vec::new();
-    let mut education_bucket: Vec<&Purchase> = Vec::new();
-    let mut travel_bucket: Vec<&Purchase> = Vec::new();
-    let mut presents_bucket: Vec<&Purchase> = Vec::new();
-    let mut style_bucket: Vec<&Purchase> = Vec::new();
-    let mut other_bucket: Vec<&Purchase> = Vec::new();
-    for purchase in purchases {
-        match purchase.product.product_type {
-            ProductType::Food => food_bucket.push(purchase),
-            ProductType::Culture => culture_bucket.push(purchase),
-            ProductType::Technology => technology_bucket.push(purchase),
-            ProductType::Education => education_bucket.push(purchase),
-            ProductType::Travel => travel_bucket.push(purchase),
-            ProductType::Presents => presents_bucket.push(purchase),
-            ProductType::Style => style_bucket.push(purchase),
-            ProductType::Other => other_bucket.push(purchase),
-        }
-    }
-    return vec![
-        Bucket::new(ProductType::Food, food_bucket),
-        Bucket::new(ProductType::Culture, culture_bucket),
-        Bucket::new(ProductType::Technology, technology_bucket),
-        Bucket::new(ProductType::Education, education_bucket),
-        Bucket::new(ProductType::Travel, travel_bucket),
-        Bucket::new(ProductType::Presents, presents_bucket),
-        Bucket::new(ProductType::Style, style_bucket),
-        Bucket::new(ProductType::Other, other_bucket),
-    ];
+fn append_line(file_name: &str, line: &str) -> std::io::Result<u64> {
+    //! Append a line to the file, creating it if it does not exist,
+    //! returning the length of the file in bytes before it was appended
+    //! The caller holds the lock of the file (see lock_file)
+    // open file in append mode, creating it if it does not exist
+    let mut file: File = OpenOptions::new()
+        .create(true)
+        .read(true)
+        .append(true)
+        .open(file_name)?;
+    let len: u64 = file.metadata()?.len();
+    // separate the line from existing ones unless a newline already does
+    if needs_separator(&mut file)? {
+        file.write_all(b"\n")?;
+    }
+    file.write_all(line.as_bytes())?;
+    Ok(len)
 }
 
-fn eval_bucket_value(bucket: &Bucket) -> f32 {
-    //! Calculate the value of a bucket
-    //! by iterating over purchases in the bucket
-    //! and adding the value of each purchase
-    return bucket
-        .purchases
-        .iter()
-        .fold(0.0, |acc, purchase| acc + purchase.value());
+fn needs_separator(file: &mut File) -> std::io::Result<bool> {
+    //! Check whether a newline must be written before a line appended to the file,
+    //! so that lines are separated by exactly one newline
+    //! That is the case unless the file is empty or already ends with a newline
+    //! (files written by fima do not, but files edited by other tools often do)
+    if file.metadata()?.len() == 0 {
+        return Ok(false);
+    }
+    let mut last: [u8; 1] = [0];
+    file.seek(SeekFrom::End(-1))?;
+    file.read_exact(&mut last)?;
+    Ok(last[0] != b'\n')
 }
 
-fn compare_buckets(buckets: Vec<Bucket>) {
-    //! Compare buckets
-    //! by iterating over buckets and printing the value of each bucket
-    // sort buckets by value
-    let mut buckets = buckets;
-    buckets.sort_by(|a, b| {
-        eval_bucket_value(b)
-            .partial_cmp(&eval_bucket_value(a))
-            .unwrap()
+fn lock_file(file_name: &str) -> std::io::Result<File> {
+    //! Take an exclusive lock for writing the file, waiting for other holders
+    //! Other fima processes writing to the same file wait until the returned handle is dropped
+    //! The lock is held on a separate file next to it (the name with .lock appended),
+    //! since the file itself is replaced when it is rewritten
+    let file: File = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(format!("{}.lock", file_name))?;
+    file.lock()?;
+    Ok(file)
+}
+
+fn replace_file(
+    file_name: &str,
+    write: impl FnOnce(&mut File) -> std::io::Result<()>,
+) -> std::io::Result<()> {
+    //! Replace the contents of the file with what write writes,
+    //! so that the file keeps its old contents if writing fails or is interrupted
+    //! The contents go to a temporary file in the same directory (the name with .tmp appended),
+    //! which is renamed over the file once complete; the caller must hold the lock of the file
+    let temp_name: String = format!("{}.tmp", file_name);
+    let result = File::create(&temp_name).and_then(|mut file| {
+        write(&mut file)?;
+        file.sync_all()
     });
-    // print buckets
-    for bucket in buckets {
-        println!(
-            "{}: {}",
-            bucket.product_type.to_string(),
-            eval_bucket_value(&bucket)
-        );
+    if let Err(error) = result {
+        let _ = std::fs::remove_file(&temp_name);
+        return Err(error);
     }
+    std::fs::rename(&temp_name, file_name)
 }
 
-fn exec_bucket_comparison() {
-    let purchases: Vec<Purchase> = read_from_file("purchase.txt");
-    let buckets: Vec<Bucket> = sort_type_buckets(&purchases);
-    compare_buckets(buckets);
+fn write_all_to_file(purchases: &[Purchase], file_name: &str) -> std::io::Result<()> {
+    //! Replace the contents of the file with the given purchases
+    //! one per line, without a trailing newline (an empty list leaves a zero-byte file)
+    //! Files ending in .gz are compressed, see gzip
+    if gzip::is_compressed(file_name) {
+        let mut contents: Vec<u8> = Vec::new();
+        write_purchases(purchases, &mut contents)?;
+        return replace_file(file_name, |file| gzip::write(file, &contents));
+    }
+    replace_file(file_name, |file| write_purchases(purchases, file))
 }
 
-enum TimeFrame {
-    Week,
-    Month,
-    Year,
+fn open_data_file(file_name: &str) -> std::io::Result<Box<dyn Read>> {
+    //! Open a data file for reading, decompressing files ending in .gz
+    let file: File = File::open(file_name)?;
+    if gzip::is_compressed(file_name) {
+        return gzip::reader(file);
+    }
+    Ok(Box::new(file))
 }
 
-fn compute_average_expenses(time_frame: TimeFrame) {
-    //! Compute average expenses per time_frame
-    //! by iterating over purchases and adding the value of each purchase
-    //! and dividing by the duration passed
-    let purchases: Vec<Purchase> = read_from_file("purchase.txt");
-    let mut total_value: f32 = 0.0;
-    let start: NaiveDate = purchases.first().unwrap().date;
-    let end: NaiveDate = purchases.last().unwrap().date;
-    let diff: i64 = end.signed_duration_since(start).num_days();
-    let duration: i64 = match time_frame {
-        TimeFrame::Week => diff / 7 + 1,
-        TimeFrame::Month => diff / 30 + 1,
-        TimeFrame::Year => diff / 365 + 1,
+// date formats accepted when entering a date, tried in order
+const DATE_FORMATS: &[&str] = &["%Y-%m-%d", "%d-%m-%Y", "%d.%m.%Y", "%m/%d/%Y"];
+
+fn parse_date(date: &str) -> Option<NaiveDate> {
+    //! Parse a date in the first of the DATE_FORMATS that matches,
+    //! or only in the date format if one was set
+    if let Some(format) = DATE_FORMAT.get() {
+        return NaiveDate::parse_from_str(date.trim(), format).ok();
+    }
+    DATE_FORMATS
+        .iter()
+        .find_map(|format| NaiveDate::parse_from_str(date.trim(), format).ok())
+}
+
+fn format_date(date: NaiveDate) -> String {
+    //! Show a date in the date format if one was set, otherwise as yyyy-mm-dd
+    match DATE_FORMAT.get() {
+        Some(format) => date.format(format).to_string(),
+        None => date.to_string(),
+    }
+}
+
+fn check_date_format(format: &str) -> Result<String, String> {
+    //! Check that a date format like "%d/%m/%Y" is valid
+    //! and gives the year, month and day, so dates shown in it can be entered again
+    let invalid = || {
+        format!(
+            "--date-format '{}' must give the year, month and day, like %d/%m/%Y",
+            format
+        )
     };
-    for purchase in purchases {
-        total_value += purchase.value();
+    if chrono::format::StrftimeItems::new(format).any(|item| item == chrono::format::Item::Error) {
+        return Err(invalid());
+    }
+    let sample: NaiveDate = NaiveDate::from_ymd_opt(2024, 3, 15).unwrap();
+    match NaiveDate::parse_from_str(&sample.format(format).to_string(), format) {
+        Ok(date) if date == sample => Ok(format.to_string()),
+        _ => Err(invalid()),
     }
-    println!(
-        "Average expenses per month: {}",
-        total_value / duration as f32
-    );
 }
 
-fn main() {
-    compute_average_expenses(TimeFrame::Week);
-    // cli_update();
-}
\ No newline at end of file
+fn prompt(message: &str, current: Option<String>) -> String {
+    //! Ask the user for a value and return the trimmed answer
+    //! If a current value is given it is shown and kept when the user just presses Enter
+    match &current {
+        Some(current) => println!("{} [{}]:", message, current),
+        None => println!("{}:", message),
+    }
+    let mut answer: String = String::new();
+    std::io::stdin().read_line(&mut answer).unwrap();
+    let answer: &str = answer.trim();
+    match current {
+        Some(current) if answer.is_empty() => current,
+        _ => answer.to_string(),
+    }
+}
+
+fn invalid_input(message: String, retry: bool) {
+    //! Report an invalid value entered at a prompt
+    //! If the value is not going to be asked for again, exit with a nonzero status
+    if !retry {
+        eprintln!("{}", message);
+        std::process::exit(1);
+    }
+    println!("{}", message);
+}
+
+fn prompt_price(current: Option<&Purchase>, retry: bool) -> (i64, Option<String>) {
+    //! Ask for a price (in cents), checking it is a non-negative decimal amount,
+    //! and the code of its currency if it is followed by one like "12.50 EUR"
+    loop {
+        let price: String = prompt("Enter price", current.map(Purchase::price_field));
+        let (amount, currency): (&str, Option<String>) = split_currency_code(&price);
+        match parse_price(amount).map(check_price) {
+            Some(Ok(price_cents)) => break (price_cents, currency),
+            Some(Err(error)) => invalid_input(format!("Invalid price: {}", error), retry),
+            None => invalid_input(
+                String::from("Price must be a decimal number like 12.34"),
+                retry,
+            ),
+        }
+    }
+}
+
+fn confirm_unit_price(price_cents: i64, quantity: Quantity, confirm_above: Option<i64>) -> i64 {
+    //! Ask whether a price making a large value (above confirm_above) is the price per item
+    //! or the total, which is a common mistake, returning the price per item
+    //! Nothing is asked without a limit or for a single item
+    let Some(limit) = confirm_above else {
+        return price_cents;
+    };
+    if quantity.milli == 1000 || quantity.value(price_cents) <= limit {
+        return price_cents;
+    }
+    let answer: String = prompt(
+        &format!(
+            "Is {} the price per item or the total of {} items? (item/total)",
+            format_cents(price_cents),
+            quantity
+        ),
+        Some(String::from("item")),
+    );
+    if answer == "total" {
+        rounding::round_div(price_cents * 1000, quantity.milli as i64)
+    } else {
+        price_cents
+    }
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    //! Levenshtein distance: the number of inserted, deleted or replaced characters
+    //! needed to turn a into b
+    let b: Vec<char> = b.chars().collect();
+    // distances from the prefix of a seen so far to every prefix of b
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, a_char) in a.chars().enumerate() {
+        let mut diagonal: usize = row[0];
+        row[0] = i + 1;
+        for (j, b_char) in b.iter().enumerate() {
+            let replaced: usize = diagonal + usize::from(a_char != *b_char);
+            diagonal = row[j + 1];
+            row[j + 1] = replaced.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+    row[b.len()]
+}
+
+fn suggest_category(name: &str) -> Option<&'static str> {
+    //! The built-in category closest to name, if it is at most 2 edits away
+    //! No suggestion is made for names that already are a category
+    if !matches!(ProductType::from_string(name), ProductType::Custom(_)) {
+        return None;
+    }
+    let name: String = name.trim().to_lowercase();
+    ProductType::all_variants()
+        .iter()
+        .filter_map(ProductType::built_in_name)
+        .map(|category| (edit_distance(&name, category), category))
+        .filter(|(distance, _)| *distance <= 2)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, category)| category)
+}
+
+fn prompt_product_type(current: Option<&ProductType>) -> ProductType {
+    //! Ask for a product type, any unknown name being a custom category
+    //! A name close to a built-in category is probably a typo, so that category is offered
+    let product_type: String = prompt(
+        "Enter product type (food, culture, technology, education, travel, presents, style, other or a custom category)",
+        current.map(ProductType::to_string),
+    );
+    if let Some(category) = suggest_category(&product_type) {
+        if prompt(&format!("Did you mean {}? (y/n)", category), None) == "y" {
+            return ProductType::from_string(category);
+        }
+    }
+    ProductType::from_string(&product_type)
+}
+
+fn prompt_quantity(current: Option<Quantity>, retry: bool) -> Quantity {
+    //! Ask for a quantity, checking it is a positive number (decimals like 1.5 are allowed)
+    loop {
+        let quantity: String = prompt(
+            "Enter quantity",
+            current.map(|quantity| quantity.to_string()),
+        );
+        match Quantity::parse(&quantity).map(check_quantity) {
+            Some(Ok(quantity)) => break quantity,
+            Some(Err(error)) => invalid_input(format!("Invalid quantity: {}", error), retry),
+            None => invalid_input(String::from("Quantity must be a number"), retry),
+        }
+    }
+}
+
+fn prompt_date(current: Option<NaiveDate>, retry: bool) -> NaiveDate {
+    //! Ask for a date in one of the DATE_FORMATS (or the date format if set), defaulting to today
+    //! A date in the future has to be confirmed
+    let default_date: NaiveDate = current.unwrap_or_else(|| Local::now().date_naive());
+    loop {
+        let formats: String = match DATE_FORMAT.get() {
+            Some(format) => format.clone(),
+            None => String::from("yyyy-mm-dd, dd-mm-yyyy, dd.mm.yyyy or mm/dd/yyyy"),
+        };
+        let date: String = prompt(
+            &format!("Enter date ({})", formats),
+            Some(format_date(default_date)),
+        );
+        match parse_date(&date) {
+            // a date in the future is most likely a typo
+            Some(date) if date > Local::now().date_naive() => {
+                let answer: String = prompt(
+                    &format!("{} is in the future, keep it? (y/n)", format_date(date)),
+                    None,
+                );
+                if answer == "y" {
+                    break date;
+                }
+            }
+            Some(date) => break date,
+            None => invalid_input(format!("Date must be in the format {}", formats), retry),
+        }
+    }
+}
+
+fn prompt_purchase(
+    current: Option<&Purchase>,
+    retry: bool,
+    confirm_above: Option<i64>,
+) -> Purchase {
+    //! Ask the user for the fields of a purchase
+    //! pre-filling each prompt with the value of the current purchase, if any
+    //! Invalid values are asked for again if retry is set, otherwise the program exits with 1
+    //! A value above confirm_above is confirmed to use the price per item, see confirm_unit_price
+    // create product
+    let name: String = prompt(
+        "Enter product name",
+        current.map(|purchase| purchase.product.name.clone()),
+    );
+    let (price_cents, currency): (i64, Option<String>) = prompt_price(current, retry);
+    let product_type: ProductType =
+        prompt_product_type(current.map(|purchase| &purchase.product.product_type));
+    // brand
+    let brand: String = prompt(
+        "Enter brand",
+        current.map(|purchase| purchase.product.brand.clone()),
+    );
+    let quantity: Quantity = prompt_quantity(current.map(|purchase| purchase.quantity), retry);
+    let price_cents: i64 = confirm_unit_price(price_cents, quantity, confirm_above);
+    let date: NaiveDate = prompt_date(current.map(|purchase| purchase.date), retry);
+    // shop
+    let shop: String = prompt("Enter shop", current.map(|purchase| purchase.shop.clone()));
+    // tags
+    let tags: String = prompt(
+        "Enter tags, separated by commas (optional)",
+        current.map(|purchase| purchase.tags.join(", ")),
+    );
+    let tags: Vec<&str> = tags.split(',').collect();
+    let note: String = prompt(
+        "Enter note (optional)",
+        current.and_then(|purchase| purchase.note.clone()),
+    );
+    // price and quantity have been checked above
+    let product = Product::new(name, price_cents, product_type, brand).unwrap();
+    Purchase::new(product, quantity, date, shop)
+        .unwrap()
+        .with_tags(&tags)
+        .with_note(&note)
+        .with_currency(currency)
+        .with_refund(current.is_some_and(|purchase| purchase.refund))
+}
+
+fn expand_recurring(purchase: &Purchase, months: u32) -> Vec<Purchase> {
+    //! Expand a monthly recurring purchase into months ordinary purchases
+    //! the first on the original date, each following one a month later
+    //! Dates past the end of a shorter month are clamped to its last day (Jan 31 -> Feb 28)
+    (0..months)
+        .filter_map(|offset| {
+            let date: NaiveDate = purchase.date.checked_add_months(Months::new(offset))?;
+            Some(Purchase {
+                date,
+                ..purchase.clone()
+            })
+        })
+        .collect()
+}
+
+fn cli_recur(store: &Store, months: Option<u32>, confirm_above: Option<i64>) {
+    //! Add a monthly recurring purchase from the command line
+    //! by asking the user for the purchase and the number of months
+    //! and writing one purchase per month
+    println!("Add a recurring purchase");
+    let purchase: Purchase = prompt_purchase(None, true, confirm_above);
+    let months: u32 = match months {
+        Some(months) => months,
+        None => loop {
+            match prompt("Enter number of months", None).parse::<u32>() {
+                Ok(months) => break months,
+                Err(_) => {
+                    println!("Number of months must be a non-negative integer");
+                    continue;
+                }
+            }
+        },
+    };
+    let expanded: Vec<Purchase> = expand_recurring(&purchase, months);
+    for purchase in &expanded {
+        or_exit_append(store.append(purchase));
+    }
+    println!("Added {} purchases", expanded.len());
+}
+
+fn write_added_summary(
+    purchases: &[Purchase],
+    currency: &Currency,
+    out: &mut dyn Write,
+) -> std::io::Result<()> {
+    //! Summarize the purchases added in one session: their number and value,
+    //! and the value and number of them per category, highest value first
+    let total: i64 = purchases.iter().map(Purchase::value).sum();
+    writeln!(
+        out,
+        "Added {} purchases worth {}",
+        purchases.len(),
+        format_amount(total, currency)
+    )?;
+    let purchases: Vec<&Purchase> = purchases.iter().collect();
+    let mut buckets: Vec<Bucket> = sort_type_buckets(&purchases);
+    buckets.sort_by_key(|bucket| std::cmp::Reverse(eval_bucket_value(bucket)));
+    for bucket in &buckets {
+        writeln!(
+            out,
+            "  {}: {} ({})",
+            bucket.product_type,
+            format_amount(eval_bucket_value(bucket), currency),
+            bucket.purchases.len()
+        )?;
+    }
+    Ok(())
+}
+
+fn cli_receipt(store: &Store, confirm_above: Option<i64>, currency: &Currency) {
+    //! Add all items of one receipt from the command line
+    //! by asking for the date and shop once and then for each item in turn
+    //! until an empty product name is entered
+    println!("Add a receipt");
+    let date: NaiveDate = prompt_date(None, true);
+    let shop: String = prompt("Enter shop", None);
+    let mut added: Vec<Purchase> = Vec::new();
+    loop {
+        let name: String = prompt("Enter product name (empty when done)", None);
+        if name.is_empty() {
+            break;
+        }
+        let (price_cents, currency): (i64, Option<String>) = prompt_price(None, true);
+        let product_type: ProductType = prompt_product_type(None);
+        let brand: String = prompt("Enter brand", None);
+        let quantity: Quantity = prompt_quantity(None, true);
+        let price_cents: i64 = confirm_unit_price(price_cents, quantity, confirm_above);
+        // price and quantity have been checked above
+        let product = Product::new(name, price_cents, product_type, brand).unwrap();
+        let purchase: Purchase = Purchase::new(product, quantity, date, shop.clone())
+            .unwrap()
+            .with_currency(currency);
+        or_exit_append(store.append(&purchase));
+        added.push(purchase);
+    }
+    or_exit_write(write_added_summary(
+        &added,
+        currency,
+        &mut std::io::stdout(),
+    ));
+}
+
+fn cli_update(
+    store: &Store,
+    dry_run: bool,
+    refund: bool,
+    confirm_above: Option<i64>,
+    currency: &Currency,
+) {
+    //! Add a purchase from the command line
+    //! by asking the user for the product name, price, quantity and date
+    //! and writing the purchase to a file once the user has confirmed it
+    //! Purchases added during the session can be undone, most recent first
+    //! On a dry run a single purchase is validated and printed as it would be stored, without writing it
+    //! With refund set, the purchases are recorded as refunds
+    if dry_run {
+        let purchase: Purchase = prompt_purchase(None, false, confirm_above).with_refund(refund);
+        match store.format {
+            Format::Json => println!("{}", serde_json::to_string_pretty(&purchase).unwrap()),
+            _ => println!("{}", purchase_to_line(&purchase)),
+        }
+        return;
+    }
+
+    // purchases added in this session with the length of the file before each was added
+    let mut added: Vec<(u64, Purchase)> = Vec::new();
+    loop {
+        println!("Add a {}", if refund { "refund" } else { "purchase" });
+        let purchase: Purchase = prompt_purchase(None, true, confirm_above).with_refund(refund);
+        print_purchase_details(&purchase, currency);
+        if prompt("Save this? (y/n)", None) == "y" {
+            let len: u64 = or_exit_append(store.append(&purchase));
+            added.push((len, purchase.clone()));
+            println!("Purchase added");
+        } else {
+            println!("Purchase discarded");
+        }
+        loop {
+            println!("Add another purchase? (y/n, u to undo the last one)");
+            let mut answer = String::new();
+            std::io::stdin().read_line(&mut answer).unwrap();
+            match answer.trim() {
+                "y" => break,
+                "u" => match added.pop() {
+                    Some((len, purchase)) => {
+                        or_exit(
+                            store
+                                .undo_append(len, &purchase)
+                                .map_err(|error| format!("could not undo: {}", error)),
+                        );
+                        println!("Removed {}", purchase_to_line(&purchase));
+                    }
+                    None => println!("Nothing to undo"),
+                },
+                _ => {
+                    // summarize sessions of several purchases
+                    if added.len() > 1 {
+                        let added: Vec<Purchase> =
+                            added.into_iter().map(|(_, purchase)| purchase).collect();
+                        or_exit_write(write_added_summary(
+                            &added,
+                            currency,
+                            &mut std::io::stdout(),
+                        ));
+                    }
+                    return;
+                }
+            }
+        }
+    }
+}
+
+fn cli_import(store: &Store, currency: &Currency) {
+    //! Append purchases piped in on stdin (in the text format) to the data file
+    //! Lines that cannot be parsed are reported and skipped
+    let (purchases, skipped) = match import_purchases(std::io::stdin().lock()) {
+        Ok(result) => result,
+        Err(error) => {
+            eprintln!("Could not import purchases: {}", error);
+            std::process::exit(1);
+        }
+    };
+    for purchase in &purchases {
+        or_exit_append(store.append(purchase));
+    }
+    for error in &skipped {
+        eprintln!("Skipped {}", error);
+    }
+    println!(
+        "Imported {} purchases, skipped {} lines",
+        purchases.len(),
+        skipped.len()
+    );
+    if !purchases.is_empty() {
+        or_exit_write(write_added_summary(
+            &purchases,
+            currency,
+            &mut std::io::stdout(),
+        ));
+    }
+}
+
+#[derive(Debug)]
+enum ReadError {
+    IoError(std::io::Error),
+    MissingField {
+        line: usize,
+    },
+    BadQuoting {
+        line: usize,
+    },
+    BadPrice {
+        line: usize,
+        value: String,
+    },
+    BadQuantity {
+        line: usize,
+        value: String,
+    },
+    BadDate {
+        line: usize,
+        value: String,
+    },
+    BadJson(serde_json::Error),
+    Invalid {
+        line: usize,
+        error: ValidationError,
+    },
+    #[cfg(feature = "sqlite")]
+    Sqlite(rusqlite::Error),
+}
+
+impl fmt::Display for ReadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ReadError::IoError(error) => write!(f, "could not read file: {}", error),
+            ReadError::MissingField { line } => write!(f, "line {}: missing field", line),
+            ReadError::BadQuoting { line } => {
+                write!(f, "line {}: quoted field is not terminated properly", line)
+            }
+            ReadError::BadPrice { line, value } => {
+                write!(f, "line {}: price '{}' is not a number", line, value)
+            }
+            ReadError::BadQuantity { line, value } => {
+                write!(f, "line {}: quantity '{}' is not a number", line, value)
+            }
+            ReadError::BadDate { line, value } => {
+                write!(f, "line {}: date '{}' cannot be parsed", line, value)
+            }
+            ReadError::BadJson(error) => write!(f, "invalid json: {}", error),
+            ReadError::Invalid { line, error } => write!(f, "line {}: {}", line, error),
+            #[cfg(feature = "sqlite")]
+            ReadError::Sqlite(error) => write!(f, "database error: {}", error),
+        }
+    }
+}
+
+impl From<std::io::Error> for ReadError {
+    fn from(error: std::io::Error) -> ReadError {
+        ReadError::IoError(error)
+    }
+}
+
+impl From<ReadError> for std::io::Error {
+    fn from(error: ReadError) -> std::io::Error {
+        //! Keep I/O errors as they are, a file that cannot be parsed is invalid data
+        match error {
+            ReadError::IoError(error) => error,
+            error => std::io::Error::new(std::io::ErrorKind::InvalidData, error.to_string()),
+        }
+    }
+}
+
+#[cfg(feature = "sqlite")]
+impl From<rusqlite::Error> for ReadError {
+    fn from(error: rusqlite::Error) -> ReadError {
+        ReadError::Sqlite(error)
+    }
+}
+
+fn read_from_file(file_name: &str) -> Result<Vec<Purchase>, ReadError> {
+    //! Read purchases from file
+    //! see read_purchases
+    read_purchases(open_data_file(file_name)?)
+}
+
+fn read_purchases_iter(file_name: &str) -> impl Iterator<Item = Result<Purchase, ReadError>> {
+    //! Stream purchases from file one line at a time, see purchases_iter
+    //! If the file cannot be opened, that error is the only item
+    let (purchases, error) = match open_data_file(file_name) {
+        Ok(file) => (Some(purchases_iter(BufReader::new(file))), None),
+        Err(error) => (None, Some(ReadError::from(error))),
+    };
+    error
+        .map(Err)
+        .into_iter()
+        .chain(purchases.into_iter().flatten())
+}
+
+fn parse_line(line: &str, line_number: usize) -> Result<Purchase, ReadError> {
+    //! Convert a single line of the data file to a Purchase
+    //! (line_number is only used in errors)
+    let Some(fields) = split_fields(line) else {
+        return Err(ReadError::BadQuoting { line: line_number });
+    };
+    let mut fields = fields.iter().map(String::as_str);
+    // assert fields have correct types
+    let (
+        Some(name),
+        Some(price),
+        Some(product_type),
+        Some(brand),
+        Some(quantity),
+        Some(date),
+        Some(shop),
+    ) = (
+        fields.next(),
+        fields.next(),
+        fields.next(),
+        fields.next(),
+        fields.next(),
+        fields.next(),
+        fields.next(),
+    )
+    else {
+        return Err(ReadError::MissingField { line: line_number });
+    };
+    let invalid = |error: ValidationError| ReadError::Invalid {
+        line: line_number,
+        error,
+    };
+    // create product and purchase
+    let (amount, currency): (&str, Option<String>) = split_currency_code(price);
+    let price_cents: i64 = parse_price(amount).ok_or_else(|| ReadError::BadPrice {
+        line: line_number,
+        value: price.to_string(),
+    })?;
+    let product = Product {
+        name: String::from(name),
+        price_cents: check_price(price_cents).map_err(invalid)?,
+        product_type: ProductType::from_string(product_type),
+        brand: brand.to_string(),
+    };
+    // refunds are written with a negative quantity
+    let (refund, unsigned): (bool, &str) = match quantity.strip_prefix('-') {
+        Some(unsigned) => (true, unsigned),
+        None => (false, quantity),
+    };
+    let quantity: Quantity = Quantity::parse(unsigned).ok_or_else(|| ReadError::BadQuantity {
+        line: line_number,
+        value: quantity.to_string(),
+    })?;
+    let purchase = Purchase {
+        product,
+        quantity: check_quantity(quantity).map_err(invalid)?,
+        date: date.parse::<NaiveDate>().map_err(|_| ReadError::BadDate {
+            line: line_number,
+            value: date.to_string(),
+        })?,
+        shop: shop.to_string(),
+        // the tags column is optional for files written before tags existed
+        tags: fields
+            .next()
+            .unwrap_or_default()
+            .split_whitespace()
+            .map(String::from)
+            .collect(),
+        refund,
+        // as is the note column, for files written before notes existed
+        note: fields
+            .next()
+            .filter(|note| !note.is_empty())
+            .map(String::from),
+        currency,
+    };
+    Ok(purchase)
+}
+
+fn purchases_iter(reader: impl BufRead) -> impl Iterator<Item = Result<Purchase, ReadError>> {
+    //! Convert each line to a Purchase as it is read,
+    //! without holding the whole input in memory
+    //! Errors carry their 1-based line number
+    //! Lines missing some fields (like a truncated last line) are skipped with a warning
+    reader
+        .lines()
+        .enumerate()
+        .filter_map(|(index, line)| match line {
+            // skip blank lines
+            Ok(line) if line.trim().is_empty() => None,
+            Ok(line) => match parse_line(&line, index + 1) {
+                Ok(purchase) => {
+                    debug!("line {}: {}", index + 1, purchase_to_line(&purchase));
+                    Some(Ok(purchase))
+                }
+                Err(error @ ReadError::MissingField { .. }) => {
+                    warn(format!("skipping {}", error));
+                    None
+                }
+                Err(error) => Some(Err(error)),
+            },
+            Err(error) => Some(Err(ReadError::from(error))),
+        })
+}
+
+fn read_purchases(reader: impl Read) -> Result<Vec<Purchase>, ReadError> {
+    //! Read purchases
+    //! by converting each line to a Purchase
+    //! and returning a vector of Purchase
+    //! or the first error encountered (with its 1-based line number)
+    purchases_iter(BufReader::new(reader)).collect()
+}
+
+fn import_purchases(reader: impl BufRead) -> Result<(Vec<Purchase>, Vec<ReadError>), ReadError> {
+    //! Read purchases line by line like read_purchases,
+    //! but collect the lines that cannot be parsed instead of stopping at the first one
+    let mut purchases: Vec<Purchase> = Vec::new();
+    let mut skipped: Vec<ReadError> = Vec::new();
+    for (index, line) in reader.lines().enumerate() {
+        let line: String = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        match parse_line(&line, index + 1) {
+            Ok(purchase) => {
+                debug!("line {}: {}", index + 1, purchase_to_line(&purchase));
+                purchases.push(purchase);
+            }
+            Err(error) => {
+                info!("skipping {}", error);
+                skipped.push(error);
+            }
+        }
+    }
+    Ok((purchases, skipped))
+}
+
+fn write_purchases_json(purchases: &[Purchase], file_name: &str) -> std::io::Result<()> {
+    //! Replace the contents of the file with the given purchases
+    //! serialized as a pretty-printed JSON array
+    let json: String = serde_json::to_string_pretty(purchases)?;
+    replace_file(file_name, |file| file.write_all(json.as_bytes()))
+}
+
+fn read_purchases_json(file_name: &str) -> Result<Vec<Purchase>, ReadError> {
+    //! Read purchases from a JSON file
+    //! An empty file is treated as containing no purchases
+    let mut file: File = File::open(file_name)?;
+    let mut contents: String = String::new();
+    file.read_to_string(&mut contents)?;
+    if contents.trim().is_empty() {
+        return Ok(Vec::new());
+    }
+    serde_json::from_str(&contents).map_err(ReadError::BadJson)
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Format {
+    Text,
+    Json,
+    #[cfg(feature = "sqlite")]
+    Sqlite,
+}
+
+struct Store {
+    file_name: String,
+    format: Format,
+    // further data files whose purchases are read after those of file_name,
+    // a store merging several files can only be read
+    merged: Vec<String>,
+}
+
+impl Store {
+    fn read_file(&self, file_name: &str) -> Result<Vec<Purchase>, ReadError> {
+        //! Read all purchases from one data file in the format of the store
+        info!("reading {}", file_name);
+        match self.format {
+            Format::Text => read_from_file(file_name),
+            Format::Json => read_purchases_json(file_name),
+            #[cfg(feature = "sqlite")]
+            Format::Sqlite => Ok(sqlite::read(&sqlite::open(file_name)?, None, None)?),
+        }
+    }
+
+    fn read(&self) -> Result<Vec<Purchase>, ReadError> {
+        //! Read all purchases from the data file
+        //! When merging several files, their purchases are concatenated
+        //! and missing files are skipped with a warning
+        if self.merged.is_empty() {
+            return self.read_file(&self.file_name);
+        }
+        let mut purchases: Vec<Purchase> = Vec::new();
+        for file_name in std::iter::once(&self.file_name).chain(&self.merged) {
+            match self.read_file(file_name) {
+                Ok(read) => purchases.extend(read),
+                Err(ReadError::IoError(error)) if error.kind() == std::io::ErrorKind::NotFound => {
+                    warn(format!("{} does not exist, skipping it", file_name));
+                }
+                Err(error) => return Err(error),
+            }
+        }
+        Ok(purchases)
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = Result<Purchase, ReadError>>> {
+        //! Iterate over all purchases
+        //! A single text data file is streamed, other stores are read at once
+        match self.format {
+            Format::Text if self.merged.is_empty() => {
+                Box::new(read_purchases_iter(&self.file_name))
+            }
+            _ => match self.read() {
+                Ok(purchases) => Box::new(purchases.into_iter().map(Ok)),
+                Err(error) => Box::new(std::iter::once(Err(error))),
+            },
+        }
+    }
+
+    fn read_between(
+        &self,
+        from: Option<NaiveDate>,
+        to: Option<NaiveDate>,
+    ) -> Result<Vec<Purchase>, ReadError> {
+        //! Read the purchases within the inclusive date range (see filter_by_date_range)
+        match self.format {
+            // the database filters by date itself
+            #[cfg(feature = "sqlite")]
+            Format::Sqlite if self.merged.is_empty() => {
+                Ok(sqlite::read(&sqlite::open(&self.file_name)?, from, to)?)
+            }
+            _ => {
+                let purchases: Vec<Purchase> = self.read()?;
+                Ok(filter_by_date_range(&purchases, from, to)
+                    .into_iter()
+                    .cloned()
+                    .collect())
+            }
+        }
+    }
+
+    fn append(&self, purchase: &Purchase) -> std::io::Result<u64> {
+        //! Add a purchase to the end of the data file
+        //! Returns the length of the store before, taken while holding the lock
+        //! (see undo_append): the size in bytes of a text file, otherwise the number of purchases
+        info!("appending {} to {}", purchase.product.name, self.file_name);
+        match self.format {
+            Format::Text => write_to_file(purchase, &self.file_name),
+            Format::Json => {
+                // JSON has no append, so rewrite the whole array
+                // without letting other processes write in between
+                let _lock: File = self.lock()?;
+                let mut purchases: Vec<Purchase> = match read_purchases_json(&self.file_name) {
+                    Ok(purchases) => purchases,
+                    Err(ReadError::IoError(error))
+                        if error.kind() == std::io::ErrorKind::NotFound =>
+                    {
+                        Vec::new()
+                    }
+                    Err(error) => return Err(error.into()),
+                };
+                let len: u64 = purchases.len() as u64;
+                purchases.push(purchase.clone());
+                write_purchases_json(&purchases, &self.file_name).map(|()| len)
+            }
+            #[cfg(feature = "sqlite")]
+            Format::Sqlite => {
+                let _lock: File = self.lock()?;
+                sqlite::open(&self.file_name)
+                    .and_then(|connection| {
+                        let len: u64 = sqlite::count(&connection)?;
+                        sqlite::append(&connection, purchase).map(|()| len)
+                    })
+                    .map_err(std::io::Error::other)
+            }
+        }
+    }
+
+    fn undo_append(&self, len: u64, purchase: &Purchase) -> Result<(), ReadError> {
+        //! Remove the purchase most recently appended,
+        //! given the length of the store that append returned for it
+        //! Nothing is removed unless it is still the last purchase and nothing was added after it
+        info!("removing the last purchase from {}", self.file_name);
+        let _lock: File = self.lock()?;
+        let changed = || {
+            ReadError::from(std::io::Error::other(
+                "the data file changed since the purchase was added, it was not removed",
+            ))
+        };
+        // the purchases of a file rewritten as a whole, if the purchase is still the only one added
+        let without_last = |purchases: Vec<Purchase>| match purchases.split_last() {
+            Some((last, rest)) if last == purchase && rest.len() as u64 == len => Ok(rest.to_vec()),
+            _ => Err(changed()),
+        };
+        match self.format {
+            Format::Text if gzip::is_compressed(&self.file_name) => {
+                let purchases: Vec<Purchase> = without_last(read_from_file(&self.file_name)?)?;
+                write_all_to_file(&purchases, &self.file_name)?;
+            }
+            // cut the file back to its previous length, restoring it exactly
+            Format::Text => {
+                let mut file: File = OpenOptions::new()
+                    .read(true)
+                    .write(true)
+                    .open(&self.file_name)?;
+                let mut appended: String = String::new();
+                file.seek(SeekFrom::Start(len))?;
+                file.read_to_string(&mut appended)?;
+                if appended.strip_prefix('\n').unwrap_or(&appended) != purchase_to_line(purchase) {
+                    return Err(changed());
+                }
+                file.set_len(len)?;
+            }
+            Format::Json => {
+                let purchases: Vec<Purchase> = without_last(read_purchases_json(&self.file_name)?)?;
+                // an empty file stays empty instead of becoming an empty array
+                if purchases.is_empty() {
+                    File::create(&self.file_name)?;
+                } else {
+                    write_purchases_json(&purchases, &self.file_name)?;
+                }
+            }
+            #[cfg(feature = "sqlite")]
+            Format::Sqlite => {
+                let connection = sqlite::open(&self.file_name)?;
+                if sqlite::count(&connection)? != len + 1 {
+                    return Err(changed());
+                }
+                sqlite::truncate(&connection, len)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn lock(&self) -> std::io::Result<File> {
+        //! Take the lock for writing the data file, see lock_file
+        //! Appending, undoing and modify take it themselves
+        lock_file(&self.file_name)
+    }
+
+    fn modify<T, E>(&self, change: impl FnOnce(&mut Vec<Purchase>) -> Result<T, E>) -> Result<T, E>
+    where
+        E: From<ReadError> + From<std::io::Error>,
+    {
+        //! Read all purchases, change them and write them back while holding the lock,
+        //! so that purchases other processes add in the meantime are not lost
+        //! Nothing is written if change fails or leaves the purchases as they were
+        //! A data file that does not exist yet holds no purchases (like a new archive)
+        let _lock: File = self.lock()?;
+        let mut purchases: Vec<Purchase> = match self.read() {
+            Ok(purchases) => purchases,
+            Err(ReadError::IoError(error)) if error.kind() == std::io::ErrorKind::NotFound => {
+                Vec::new()
+            }
+            Err(error) => return Err(error.into()),
+        };
+        let before: Vec<Purchase> = purchases.clone();
+        let result: T = change(&mut purchases)?;
+        if purchases != before {
+            self.write_all(&purchases)?;
+        }
+        Ok(result)
+    }
+
+    fn write_all(&self, purchases: &[Purchase]) -> std::io::Result<()> {
+        //! Replace the contents of the data file with the given purchases
+        //! The caller holds the lock (see modify), so concurrent writers cannot interleave
+        info!(
+            "writing {} purchases to {}",
+            purchases.len(),
+            self.file_name
+        );
+        match self.format {
+            Format::Text => write_all_to_file(purchases, &self.file_name),
+            Format::Json => write_purchases_json(purchases, &self.file_name),
+            #[cfg(feature = "sqlite")]
+            Format::Sqlite => sqlite::open(&self.file_name)
+                .and_then(|mut connection| sqlite::replace_all(&mut connection, purchases))
+                .map_err(std::io::Error::other),
+        }
+    }
+}
+
+#[derive(Debug)]
+enum DeleteError {
+    Read(ReadError),
+    IndexOutOfRange { index: usize, len: usize },
+    Write(std::io::Error),
+}
+
+impl fmt::Display for DeleteError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DeleteError::Read(error) => write!(f, "{}", error),
+            DeleteError::IndexOutOfRange { index, len } => write!(
+                f,
+                "index {} is out of range ({} purchases recorded)",
+                index, len
+            ),
+            DeleteError::Write(error) => write!(f, "could not write file: {}", error),
+        }
+    }
+}
+
+impl From<ReadError> for DeleteError {
+    fn from(error: ReadError) -> DeleteError {
+        DeleteError::Read(error)
+    }
+}
+
+impl From<std::io::Error> for DeleteError {
+    fn from(error: std::io::Error) -> DeleteError {
+        DeleteError::Write(error)
+    }
+}
+
+fn delete_purchase(store: &Store, index: usize) -> Result<Purchase, DeleteError> {
+    //! Delete the purchase at the given zero-based index
+    //! by reading all purchases, removing the one at index and rewriting the file
+    //! The file is left untouched if the index is out of range
+    store.modify(|purchases| {
+        if index >= purchases.len() {
+            return Err(DeleteError::IndexOutOfRange {
+                index,
+                len: purchases.len(),
+            });
+        }
+        Ok(purchases.remove(index))
+    })
+}
+
+fn print_indexed_purchases(purchases: &[Purchase]) {
+    //! Print purchases together with their zero-based index
+    for (index, purchase) in purchases.iter().enumerate() {
+        println!("[{}] {}", index, purchase_to_line(purchase));
+    }
+}
+
+fn cli_delete(store: &Store) {
+    //! Delete a purchase from the command line
+    //! by listing purchases with their indices
+    //! and asking the user for the index to remove
+    let Some(purchases) = load_purchases(store) else {
+        return;
+    };
+    if purchases.is_empty() {
+        println!("No purchases recorded yet");
+        return;
+    }
+    print_indexed_purchases(&purchases);
+    // check index is an integer
+    let index: usize = loop {
+        println!("Enter index of purchase to delete:");
+        let mut index: String = String::new();
+        std::io::stdin().read_line(&mut index).unwrap();
+        match index.trim().parse::<usize>() {
+            Ok(index) => break index,
+            Err(_) => {
+                println!("Index must be a non-negative integer");
+                continue;
+            }
+        }
+    };
+    match delete_purchase(store, index) {
+        Ok(purchase) => println!("Deleted: {}", purchase_to_line(&purchase)),
+        Err(error) => println!("Could not delete purchase: {}", error),
+    }
+}
+
+#[derive(Debug)]
+enum EditError {
+    Read(ReadError),
+    IndexOutOfRange { index: usize, len: usize },
+    Write(std::io::Error),
+}
+
+impl fmt::Display for EditError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EditError::Read(error) => write!(f, "{}", error),
+            EditError::IndexOutOfRange { index, len } => write!(
+                f,
+                "index {} is out of range ({} purchases recorded)",
+                index, len
+            ),
+            EditError::Write(error) => write!(f, "could not write file: {}", error),
+        }
+    }
+}
+
+impl From<ReadError> for EditError {
+    fn from(error: ReadError) -> EditError {
+        EditError::Read(error)
+    }
+}
+
+impl From<std::io::Error> for EditError {
+    fn from(error: std::io::Error) -> EditError {
+        EditError::Write(error)
+    }
+}
+
+fn edit_purchase(store: &Store, index: usize, new: Purchase) -> Result<(), EditError> {
+    //! Replace the purchase at the given zero-based index
+    //! by reading all purchases, swapping in the new one and rewriting the file
+    //! The file is left untouched if the index is out of range
+    store.modify(|purchases| {
+        let len: usize = purchases.len();
+        let Some(purchase) = purchases.get_mut(index) else {
+            return Err(EditError::IndexOutOfRange { index, len });
+        };
+        *purchase = new;
+        Ok(())
+    })
+}
+
+fn cli_edit(store: &Store, confirm_above: Option<i64>) {
+    //! Edit a purchase from the command line
+    //! by listing purchases with their indices, asking the user for the index to change
+    //! and then for the new values, pressing Enter to keep the current one
+    let Some(purchases) = load_purchases(store) else {
+        return;
+    };
+    if purchases.is_empty() {
+        println!("No purchases recorded yet");
+        return;
+    }
+    print_indexed_purchases(&purchases);
+    // check index is an integer
+    let index: usize = loop {
+        match prompt("Enter index of purchase to edit", None).parse::<usize>() {
+            Ok(index) => break index,
+            Err(_) => {
+                println!("Index must be a non-negative integer");
+                continue;
+            }
+        }
+    };
+    let Some(current) = purchases.get(index) else {
+        let error = EditError::IndexOutOfRange {
+            index,
+            len: purchases.len(),
+        };
+        println!("Could not edit purchase: {}", error);
+        return;
+    };
+    let purchase: Purchase = prompt_purchase(Some(current), true, confirm_above);
+    let line: String = purchase_to_line(&purchase);
+    match edit_purchase(store, index, purchase) {
+        Ok(()) => println!("Updated: {}", line),
+        Err(error) => println!("Could not edit purchase: {}", error),
+    }
+}
+
+struct Bucket<'a> {
+    product_type: ProductType,
+    purchases: Vec<&'a Purchase>,
+}
+
+impl<'a> Bucket<'a> {
+    fn new(product_type: ProductType, purchases: Vec<&'a Purchase>) -> Bucket<'a> {
+        //! Create a new bucket
+        //! by passing in a product_type and purchases
+        Bucket {
+            product_type,
+            purchases,
+        }
+    }
+}
+
+fn sort_type_buckets<'a>(purchases: &[&'a Purchase]) -> Vec<Bucket<'a>> {
+    //! Sort purchases into buckets by product type
+    //! by iterating over purchases and adding each purchase to the bucket of its type,
+    //! creating a bucket for every distinct type seen (in order of first appearance)
+    //! and returning a vector of buckets
+    let mut buckets: Vec<Bucket> = Vec::new();
+    for &purchase in purchases {
+        match buckets
+            .iter_mut()
+            .find(|bucket| bucket.product_type == purchase.product.product_type)
+        {
+            Some(bucket) => bucket.purchases.push(purchase),
+            None => buckets.push(Bucket::new(
+                purchase.product.product_type.clone(),
+                vec![purchase],
+            )),
+        }
+    }
+    buckets
+}
+
+fn eval_bucket_value(bucket: &Bucket) -> i64 {
+    //! Calculate the value of a bucket in cents
+    //! by iterating over purchases in the bucket
+    //! and adding the value of each purchase, so refunds are subtracted
+    bucket
+        .purchases
+        .iter()
+        .fold(0, |acc, purchase| acc + purchase.value())
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct BucketStats {
+    total: i64,
+    count: usize,
+    average: i64,
+    // value of the refunds included in total, as a positive amount
+    refunded: i64,
+}
+
+fn eval_bucket_stats(bucket: &Bucket) -> BucketStats {
+    //! Calculate the total value (in cents, net of refunds), the number of purchases
+    //! and the average value per purchase of a bucket, and how much was refunded
+    //! An empty bucket has an average of 0
+    let total: i64 = eval_bucket_value(bucket);
+    let refunded: i64 = -bucket
+        .purchases
+        .iter()
+        .filter(|purchase| purchase.refund)
+        .map(|purchase| purchase.value())
+        .sum::<i64>();
+    let count: usize = bucket.purchases.len();
+    let average: i64 = if count == 0 {
+        0
+    } else {
+        rounding::round_cents(total as f64 / count as f64)
+    };
+    BucketStats {
+        total,
+        count,
+        average,
+        refunded,
+    }
+}
+
+fn eval_bucket_units(bucket: &Bucket) -> Quantity {
+    //! Calculate the number of units in a bucket
+    //! by adding the quantity of each purchase
+    bucket
+        .purchases
+        .iter()
+        .fold(Quantity::default(), |acc, purchase| acc + purchase.quantity)
+}
+
+struct ReportOptions {
+    from: Option<NaiveDate>,
+    to: Option<NaiveDate>,
+    top: Option<usize>,
+    units: bool,
+    chart: Option<usize>,
+    // reference year and price index per year to adjust values for inflation
+    adjust_to: Option<(i32, HashMap<i32, i64>)>,
+    json: bool,
+    // share of the total (in percent) from which the other category lists its products
+    expand_other: f64,
+}
+
+// default for ReportOptions::expand_other
+const DEFAULT_EXPAND_OTHER: f64 = 10.0;
+
+fn product_totals(bucket: &Bucket) -> Vec<(String, i64)> {
+    //! Total value (in cents) of each distinct product in a bucket, highest first
+    //! Products are told apart by name ignoring case and shown as first written
+    let mut totals: Vec<(String, String, i64)> = Vec::new();
+    for purchase in &bucket.purchases {
+        let key: String = purchase.product.key();
+        match totals.iter_mut().find(|(other, _, _)| *other == key) {
+            Some((_, _, total)) => *total += purchase.value(),
+            None => totals.push((key, purchase.product.name.clone(), purchase.value())),
+        }
+    }
+    // sort is stable, so ties keep the order of first appearance
+    totals.sort_by_key(|(_, _, total)| std::cmp::Reverse(*total));
+    totals
+        .into_iter()
+        .map(|(_, name, total)| (name, total))
+        .collect()
+}
+
+fn bar(value: i64, max: i64, width: usize) -> String {
+    //! Horizontal bar for value, scaled so that max fills width characters
+    //! Values of zero or less get no bar
+    if value <= 0 || max <= 0 {
+        return String::new();
+    }
+    let len: usize = (value as f64 / max as f64 * width as f64).round() as usize;
+    "█".repeat(len)
+}
+
+// what the report shows of a category, which can be cached (see exec_bucket_comparison)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct CategorySummary {
+    product_type: ProductType,
+    stats: BucketStats,
+    units: Quantity,
+    // value of each product, only kept for the other category (see compare_buckets)
+    products: Vec<(String, i64)>,
+}
+
+fn summarize_bucket(bucket: &Bucket) -> CategorySummary {
+    //! Everything the report shows of a bucket
+    let products: Vec<(String, i64)> = if bucket.product_type == ProductType::Other {
+        product_totals(bucket)
+    } else {
+        Vec::new()
+    };
+    CategorySummary {
+        product_type: bucket.product_type.clone(),
+        stats: eval_bucket_stats(bucket),
+        units: eval_bucket_units(bucket),
+        products,
+    }
+}
+
+impl From<Bucket<'_>> for CategorySummary {
+    fn from(bucket: Bucket) -> CategorySummary {
+        summarize_bucket(&bucket)
+    }
+}
+
+fn report_json(ranked: &[CategorySummary], currency: &Currency) -> String {
+    //! Machine-readable report: the total and the value of each category
+    //! as amounts in currency units, e.g. {"total": 3.5, "categories": {"food": 3.5}, "currency": "USD"}
+    //! The currency is the ISO 4217 code, or null if the symbol shown has no known code
+    let amount = |cents: i64| serde_json::Value::from(cents as f64 / 100.0);
+    let categories: serde_json::Map<String, serde_json::Value> = ranked
+        .iter()
+        .map(|category| {
+            (
+                category.product_type.to_string(),
+                amount(category.stats.total),
+            )
+        })
+        .collect();
+    let total: i64 = ranked.iter().map(|category| category.stats.total).sum();
+    serde_json::json!({
+        "total": amount(total),
+        "categories": categories,
+        "currency": currency.code(),
+    })
+    .to_string()
+}
+
+fn compare_buckets<B: Into<CategorySummary>>(
+    buckets: Vec<B>,
+    options: &ReportOptions,
+    currency: &Currency,
+    out: &mut dyn Write,
+) -> std::io::Result<()> {
+    //! Compare buckets, given as they are or summarized (e.g. from the cache)
+    //! by iterating over buckets and printing the value of each category
+    //! with its share of the total, its number of purchases and their average value,
+    //! followed by the total itself
+    //! With top given, only the top highest categories are listed and the rest are summarized
+    //! With units set, the number of units bought is shown next to each value
+    //! With chart set, the listed categories are also drawn as bars at most chart characters wide
+    //! With json set, the total and the value of every category are written as a JSON object instead
+    // summarize buckets once, leaving out empty ones
+    let mut ranked: Vec<CategorySummary> = buckets
+        .into_iter()
+        .map(Into::into)
+        .filter(|category: &CategorySummary| category.stats.count > 0)
+        .collect();
+    if options.json {
+        return writeln!(out, "{}", report_json(&ranked, currency));
+    }
+    if ranked.is_empty() {
+        writeln!(out, "No purchases recorded")?;
+        return Ok(());
+    }
+    let total: i64 = ranked.iter().map(|category| category.stats.total).sum();
+    let refunded: i64 = ranked.iter().map(|category| category.stats.refunded).sum();
+    // sort categories by value
+    ranked.sort_by_key(|category| std::cmp::Reverse(category.stats.total));
+    // shares of all categories add up to 100%, including those summarized below
+    let values: Vec<i64> = ranked.iter().map(|category| category.stats.total).collect();
+    let shares: Vec<f64> = rounding::percentages(&values);
+    let rest: Vec<CategorySummary> = match options.top {
+        Some(top) if top < ranked.len() => ranked.split_off(top),
+        _ => Vec::new(),
+    };
+    // print categories
+    for (category, share) in ranked.iter().zip(shares) {
+        let stats: &BucketStats = &category.stats;
+        let units: String = if options.units {
+            format!(" over {} items", category.units)
+        } else {
+            String::new()
+        };
+        // net values are listed, with the gross spend where something was refunded
+        let refunds: String = if stats.refunded > 0 {
+            format!(
+                ", gross {} of which {} refunded",
+                format_amount(stats.total + stats.refunded, currency),
+                format_amount(stats.refunded, currency)
+            )
+        } else {
+            String::new()
+        };
+        writeln!(
+            out,
+            "{}: {}{} ({:.1}%), {} purchases, avg {}{}",
+            category.product_type,
+            format_amount(stats.total, currency),
+            units,
+            share,
+            stats.count,
+            format_amount(stats.average, currency),
+            refunds
+        )?;
+        // a large other category is broken down to hint at categories worth adding
+        if category.product_type == ProductType::Other && share >= options.expand_other {
+            for (name, total) in &category.products {
+                writeln!(out, "  {}: {}", name, format_amount(*total, currency))?;
+            }
+        }
+    }
+    if !rest.is_empty() {
+        let rest_total: i64 = rest.iter().map(|category| category.stats.total).sum();
+        writeln!(
+            out,
+            "… and {} more: {}",
+            rest.len(),
+            format_amount(rest_total, currency)
+        )?;
+    }
+    let mut total_line: String = format!("Total: {}", format_amount(total, currency));
+    if refunded > 0 {
+        total_line += &format!(" net, {} gross", format_amount(total + refunded, currency));
+    }
+    writeln!(out, "{}", color::bold(&total_line))?;
+    // draw categories, the ranking puts the largest first
+    if let Some(width) = options.chart {
+        let max: i64 = ranked.first().map_or(0, |category| category.stats.total);
+        let labels: Vec<String> = ranked
+            .iter()
+            .map(|category| {
+                format!(
+                    "{} {}",
+                    category.product_type,
+                    format_amount(category.stats.total, currency)
+                )
+            })
+            .collect();
+        let label_width: usize = labels
+            .iter()
+            .map(|label| label.chars().count())
+            .max()
+            .unwrap_or(0);
+        writeln!(out)?;
+        for (label, category) in labels.iter().zip(&ranked) {
+            writeln!(
+                out,
+                "{:<label_width$} |{}",
+                label,
+                bar(category.stats.total, max, width)
+            )?;
+        }
+    }
+    Ok(())
+}
+
+fn filter_by_date_range(
+    purchases: &[Purchase],
+    from: Option<NaiveDate>,
+    to: Option<NaiveDate>,
+) -> Vec<&Purchase> {
+    //! Filter purchases by date
+    //! keeping those within the inclusive range from..=to
+    //! A missing bound leaves that side of the range open
+    purchases
+        .iter()
+        .filter(|purchase| from.is_none_or(|from| purchase.date >= from))
+        .filter(|purchase| to.is_none_or(|to| purchase.date <= to))
+        .collect()
+}
+
+fn load_purchases(store: &Store) -> Option<Vec<Purchase>> {
+    //! Read all purchases from the store, see load_purchases_between
+    load_purchases_between(store, None, None)
+}
+
+fn load_purchases_between(
+    store: &Store,
+    from: Option<NaiveDate>,
+    to: Option<NaiveDate>,
+) -> Option<Vec<Purchase>> {
+    //! Read the purchases within the inclusive date range from the store
+    //! printing a friendly message instead of failing if they cannot be loaded
+    //! A data file that does not exist yet holds no purchases; other IO errors
+    //! (like missing permissions) are still reported
+    //! Purchases dated in the future are loaded, but a warning is printed for each of them
+    match store.read_between(from, to) {
+        Ok(mut purchases) => {
+            // before converting, so the purchases are shown as they are stored
+            let today: NaiveDate = Local::now().date_naive();
+            let future: Vec<(String, NaiveDate)> = future_purchases(&purchases, today);
+            warn_future(&future, today);
+            FUTURE_PURCHASES.lock().unwrap().extend(future);
+            if from.is_some() || to.is_some() {
+                info!(
+                    "{} purchases dated from {} to {}",
+                    purchases.len(),
+                    from.map_or(String::from("the first"), |from| from.to_string()),
+                    to.map_or(String::from("the last"), |to| to.to_string())
+                );
+            }
+            if let Some(rates) = EXCHANGE_RATES.get() {
+                if !rates.is_empty() {
+                    info!("converting prices with {} exchange rates", rates.len());
+                }
+                for code in convert_currencies(&mut purchases, rates) {
+                    warn(format!(
+                        "no exchange rate for {}, its prices are not converted",
+                        code
+                    ));
+                }
+            }
+            Some(purchases)
+        }
+        Err(ReadError::IoError(error)) if error.kind() == std::io::ErrorKind::NotFound => {
+            Some(Vec::new())
+        }
+        Err(error) => {
+            println!("Could not load purchases: {}", error);
+            None
+        }
+    }
+}
+
+fn future_purchases(purchases: &[Purchase], after: NaiveDate) -> Vec<(String, NaiveDate)> {
+    //! The purchases dated after the given day, as lines of the data file with their date
+    //! (a line identifies the purchase however the purchases were filtered or merged)
+    purchases
+        .iter()
+        .filter(|purchase| purchase.date > after)
+        .map(|purchase| (purchase_to_line(purchase), purchase.date))
+        .collect()
+}
+
+fn warn_future(future: &[(String, NaiveDate)], today: NaiveDate) {
+    //! Print a warning for each of the purchases (see future_purchases) still dated after today
+    for (line, date) in future {
+        if *date > today {
+            eprintln!("Warning: purchase dated in the future ({}): {}", date, line);
+        }
+    }
+}
+
+fn read_inflation_index(file_name: &str) -> Result<HashMap<i32, i64>, String> {
+    //! Read a price index per year from a "year, index" file, e.g. "2024, 118.5"
+    //! Index values are kept in hundredths like amounts
+    let entries: Vec<(String, i64)> = read_key_amounts(file_name)?
+        .ok_or_else(|| format!("inflation index {} does not exist", file_name))?;
+    entries
+        .into_iter()
+        .map(|(year, index)| match year.parse::<i32>() {
+            Ok(year) if index > 0 => Ok((year, index)),
+            _ => Err(format!(
+                "{}: expected \"year, index\" with a positive index, got \"{}\"",
+                file_name, year
+            )),
+        })
+        .collect()
+}
+
+fn adjust_for_inflation(
+    purchases: &[Purchase],
+    year: i32,
+    index: &HashMap<i32, i64>,
+) -> (Vec<Purchase>, Vec<i32>) {
+    //! Scale the price of each purchase to the price level of the reference year
+    //! Purchases from years without an index entry are left as they are;
+    //! those years are returned alongside the adjusted purchases
+    let reference: i64 = index[&year];
+    let mut missing: Vec<i32> = Vec::new();
+    let adjusted: Vec<Purchase> = purchases
+        .iter()
+        .map(|purchase| {
+            let mut purchase: Purchase = purchase.clone();
+            match index.get(&purchase.date.year()) {
+                Some(level) => {
+                    purchase.product.price_cents = rounding::round_cents(
+                        purchase.product.price_cents as f64 * reference as f64 / *level as f64,
+                    );
+                }
+                None if !missing.contains(&purchase.date.year()) => {
+                    missing.push(purchase.date.year())
+                }
+                None => {}
+            }
+            purchase
+        })
+        .collect();
+    (adjusted, missing)
+}
+
+// cached report categories with the key they were computed for, see exec_bucket_comparison
+// and the warnings printed while reading the purchases, repeated when the cache is used
+#[derive(Serialize, Deserialize)]
+struct ReportCache {
+    key: u64,
+    categories: Vec<CategorySummary>,
+    warnings: Vec<String>,
+    // purchases dated after the day the cache was written, see future_purchases
+    future: Vec<(String, NaiveDate)>,
+}
+
+// changed whenever ReportCache or CategorySummary change, so older caches are not read
+const CACHE_VERSION: u32 = 3;
+
+fn cache_key(contents: &[u8]) -> u64 {
+    //! Hash of everything the report's categories depend on besides the options:
+    //! the contents of the data file and the aliases and exchange rates in use
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    CACHE_VERSION.hash(&mut hasher);
+    contents.hash(&mut hasher);
+    let mut aliases: Vec<(&String, &ProductType)> =
+        USER_ALIASES.get().into_iter().flatten().collect();
+    aliases.sort();
+    aliases.hash(&mut hasher);
+    let mut rates: Vec<(&String, &i64)> = EXCHANGE_RATES.get().into_iter().flatten().collect();
+    rates.sort();
+    rates.hash(&mut hasher);
+    hasher.finish()
+}
+
+fn read_cache(file_name: &str, key: u64) -> Option<ReportCache> {
+    //! The cache, if it exists and was computed for the key
+    let contents: String = std::fs::read_to_string(file_name).ok()?;
+    let cache: ReportCache = serde_json::from_str(&contents).ok()?;
+    (cache.key == key).then_some(cache)
+}
+
+fn exec_bucket_comparison(
+    store: &Store,
+    options: &ReportOptions,
+    cache: bool,
+    currency: &Currency,
+    out: &mut dyn Write,
+) -> std::io::Result<()> {
+    //! Compare the spending per category of the purchases in the range of the options
+    //! With cache set and neither a date range nor an inflation adjustment, the categories
+    //! are kept in a cache file next to the data file and reused while its key
+    //! (see cache_key) stays the same; the warnings of reading the purchases are repeated
+    let cache: Option<(String, u64)> = if cache
+        && store.merged.is_empty()
+        && options.from.is_none()
+        && options.to.is_none()
+        && options.adjust_to.is_none()
+    {
+        std::fs::read(&store.file_name)
+            .ok()
+            .map(|contents| (format!("{}.cache", store.file_name), cache_key(&contents)))
+    } else {
+        None
+    };
+    if let Some((file_name, key)) = &cache {
+        if let Some(cache) = read_cache(file_name, *key) {
+            for warning in &cache.warnings {
+                eprintln!("Warning: {}", warning);
+            }
+            warn_future(&cache.future, Local::now().date_naive());
+            if cache.categories.is_empty() && !options.json {
+                return writeln!(out, "No purchases recorded yet");
+            }
+            return compare_buckets(cache.categories, options, currency, out);
+        }
+    }
+    let warned: usize = WARNINGS.lock().unwrap().len();
+    let dated: usize = FUTURE_PURCHASES.lock().unwrap().len();
+    let Some(purchases) = load_purchases_between(store, options.from, options.to) else {
+        return Ok(());
+    };
+    if purchases.is_empty() && options.from.is_none() && options.to.is_none() && !options.json {
+        writeln!(out, "No purchases recorded yet")?;
+        return Ok(());
+    }
+    let purchases: Vec<Purchase> = match &options.adjust_to {
+        Some((year, index)) => {
+            let (adjusted, missing) = adjust_for_inflation(&purchases, *year, index);
+            for missing in missing {
+                eprintln!(
+                    "Warning: no inflation index for {}, its purchases are not adjusted",
+                    missing
+                );
+            }
+            adjusted
+        }
+        None => purchases,
+    };
+    let purchases: Vec<&Purchase> = purchases.iter().collect();
+    let categories: Vec<CategorySummary> = sort_type_buckets(&purchases)
+        .iter()
+        .map(summarize_bucket)
+        .collect();
+    if let Some((file_name, key)) = cache {
+        let cache = ReportCache {
+            key,
+            categories: categories.clone(),
+            warnings: WARNINGS.lock().unwrap()[warned..].to_vec(),
+            future: FUTURE_PURCHASES.lock().unwrap()[dated..].to_vec(),
+        };
+        // the cache only saves time, so failing to write it is not an error
+        let _ = serde_json::to_string(&cache)
+            .map_err(std::io::Error::from)
+            .and_then(|json| {
+                let _lock: File = lock_file(&file_name)?;
+                replace_file(&file_name, |file| file.write_all(json.as_bytes()))
+            });
+    }
+    compare_buckets(categories, options, currency, out)
+}
+
+fn monthly_totals(purchases: &[Purchase]) -> BTreeMap<(i32, u32), i64> {
+    //! Sum the value of purchases (in cents) per calendar month
+    //! keyed by (year, month), so iterating yields chronological order
+    //! Months without purchases are absent
+    let mut totals: BTreeMap<(i32, u32), i64> = BTreeMap::new();
+    for purchase in purchases {
+        *totals
+            .entry((purchase.date.year(), purchase.date.month()))
+            .or_insert(0) += purchase.value();
+    }
+    totals
+}
+
+fn weekly_totals(purchases: &[Purchase]) -> BTreeMap<(i32, u32), i64> {
+    //! Sum the value of purchases (in cents) per ISO week
+    //! keyed by (ISO year, week), so iterating yields chronological order
+    //! The ISO year can differ from the calendar year around New Year,
+    //! e.g. 2024-12-30 belongs to week 1 of 2025
+    let mut totals: BTreeMap<(i32, u32), i64> = BTreeMap::new();
+    for purchase in purchases {
+        let week = purchase.date.iso_week();
+        *totals.entry((week.year(), week.week())).or_insert(0) += purchase.value();
+    }
+    totals
+}
+
+fn exec_weekly(store: &Store, currency: &Currency) {
+    //! Print the total spending of each ISO week in chronological order
+    let Some(purchases) = load_purchases(store) else {
+        return;
+    };
+    if purchases.is_empty() {
+        println!("No purchases recorded yet");
+        return;
+    }
+    for ((year, week), total) in weekly_totals(&purchases) {
+        println!("{}-W{:02}: {}", year, week, format_amount(total, currency));
+    }
+}
+
+fn quarter_of(date: NaiveDate) -> (i32, u8) {
+    //! Calendar quarter (1 to 4) of a date with its year
+    (date.year(), ((date.month() - 1) / 3 + 1) as u8)
+}
+
+fn quarterly_totals(purchases: &[Purchase]) -> BTreeMap<(i32, u8), i64> {
+    //! Sum the value of purchases (in cents) per calendar quarter
+    //! keyed by (year, quarter), so iterating yields chronological order
+    //! Quarters without purchases are absent
+    let mut totals: BTreeMap<(i32, u8), i64> = BTreeMap::new();
+    for purchase in purchases {
+        *totals.entry(quarter_of(purchase.date)).or_insert(0) += purchase.value();
+    }
+    totals
+}
+
+fn exec_quarterly(store: &Store, by_category: bool, currency: &Currency) {
+    //! Print the total spending of each calendar quarter in chronological order,
+    //! with by_category also the spending per category in it, highest first
+    let Some(purchases) = load_purchases(store) else {
+        return;
+    };
+    if purchases.is_empty() {
+        println!("No purchases recorded yet");
+        return;
+    }
+    for ((year, quarter), total) in quarterly_totals(&purchases) {
+        println!("{}-Q{}: {}", year, quarter, format_amount(total, currency));
+        if !by_category {
+            continue;
+        }
+        let in_quarter: Vec<&Purchase> = purchases
+            .iter()
+            .filter(|purchase| quarter_of(purchase.date) == (year, quarter))
+            .collect();
+        let mut categories: Vec<(ProductType, i64)> = sort_type_buckets(&in_quarter)
+            .iter()
+            .map(|bucket| (bucket.product_type.clone(), eval_bucket_value(bucket)))
+            .collect();
+        categories.sort_by_key(|(_, value)| std::cmp::Reverse(*value));
+        for (category, value) in categories {
+            println!("  {}: {}", category, format_amount(value, currency));
+        }
+    }
+}
+
+fn cumulative_totals(purchases: &[Purchase]) -> Vec<(NaiveDate, i64)> {
+    //! Running total of spending (in cents) up to and including each date with purchases,
+    //! in chronological order; purchases on the same date make a single step
+    let mut daily: BTreeMap<NaiveDate, i64> = BTreeMap::new();
+    for purchase in purchases {
+        *daily.entry(purchase.date).or_insert(0) += purchase.value();
+    }
+    daily
+        .into_iter()
+        .scan(0, |total, (date, value)| {
+            *total += value;
+            Some((date, *total))
+        })
+        .collect()
+}
+
+fn exec_cumulative(store: &Store, chart: Option<usize>, currency: &Currency) {
+    //! Print the cumulative spending at each date with purchases,
+    //! optionally drawn as bars that rise step by step
+    let Some(purchases) = load_purchases(store) else {
+        return;
+    };
+    let steps: Vec<(NaiveDate, i64)> = cumulative_totals(&purchases);
+    if steps.is_empty() {
+        println!("No purchases recorded yet");
+        return;
+    }
+    let lines: Vec<String> = steps
+        .iter()
+        .map(|(date, total)| format!("{}: {}", date, format_amount(*total, currency)))
+        .collect();
+    let Some(width) = chart else {
+        lines.iter().for_each(|line| println!("{}", line));
+        return;
+    };
+    let line_width: usize = lines
+        .iter()
+        .map(|line| line.chars().count())
+        .max()
+        .unwrap_or(0);
+    let max: i64 = steps.iter().map(|(_, total)| *total).max().unwrap_or(0);
+    for (line, (_, total)) in lines.iter().zip(&steps) {
+        println!("{:<line_width$} |{}", line, bar(*total, max, width));
+    }
+}
+
+fn weekday_totals(purchases: &[Purchase]) -> [i64; 7] {
+    //! Sum the value of purchases (in cents) per day of the week, Monday first
+    let mut totals: [i64; 7] = [0; 7];
+    for purchase in purchases {
+        totals[purchase.date.weekday().num_days_from_monday() as usize] += purchase.value();
+    }
+    totals
+}
+
+fn weekday_counts(first: NaiveDate, last: NaiveDate) -> [i64; 7] {
+    //! Count how often each day of the week (Monday first) occurs from first to last inclusive
+    let mut counts: [i64; 7] = [0; 7];
+    for date in first.iter_days().take_while(|date| *date <= last) {
+        counts[date.weekday().num_days_from_monday() as usize] += 1;
+    }
+    counts
+}
+
+fn exec_weekdays(store: &Store, chart: Option<usize>, currency: &Currency) {
+    //! Print the total spending per day of the week and its average per such day
+    //! between the first and the last purchase, optionally drawn as bars
+    let Some(purchases) = load_purchases(store) else {
+        return;
+    };
+    let (Some(first), Some(last)) = (
+        purchases.iter().map(|purchase| purchase.date).min(),
+        purchases.iter().map(|purchase| purchase.date).max(),
+    ) else {
+        println!("No purchases recorded yet");
+        return;
+    };
+    let totals: [i64; 7] = weekday_totals(&purchases);
+    let counts: [i64; 7] = weekday_counts(first, last);
+    let lines: Vec<String> = totals
+        .iter()
+        .zip(counts)
+        .enumerate()
+        .map(|(index, (total, count))| {
+            // every weekday occurs in the range unless it is shorter than a week
+            let average: i64 = if count == 0 {
+                0
+            } else {
+                rounding::round_div(*total, count)
+            };
+            format!(
+                "{}: {}, avg {}",
+                chrono::Weekday::try_from(index as u8).unwrap(),
+                format_amount(*total, currency),
+                format_amount(average, currency)
+            )
+        })
+        .collect();
+    let Some(width) = chart else {
+        lines.iter().for_each(|line| println!("{}", line));
+        return;
+    };
+    let line_width: usize = lines
+        .iter()
+        .map(|line| line.chars().count())
+        .max()
+        .unwrap_or(0);
+    let max: i64 = totals.iter().copied().max().unwrap_or(0);
+    for (line, total) in lines.iter().zip(totals) {
+        println!("{:<line_width$} |{}", line, bar(total, max, width));
+    }
+}
+
+fn day_totals(purchases: &[Purchase]) -> Vec<(NaiveDate, i64)> {
+    //! Sum the value of purchases (in cents) per date, for every date
+    //! from the first to the last purchase in order; dates without purchases are zero
+    let mut totals: BTreeMap<NaiveDate, i64> = BTreeMap::new();
+    for purchase in purchases {
+        *totals.entry(purchase.date).or_insert(0) += purchase.value();
+    }
+    let (Some(first), Some(last)) = (totals.keys().next(), totals.keys().next_back()) else {
+        return Vec::new();
+    };
+    first
+        .iter_days()
+        .take_while(|date| date <= last)
+        .map(|date| (date, totals.get(&date).copied().unwrap_or(0)))
+        .collect()
+}
+
+fn exec_days(store: &Store, top: usize, currency: &Currency) {
+    //! Print the top dates with the highest and the lowest spending
+    //! and how many dates between the first and the last purchase had none
+    let Some(purchases) = load_purchases(store) else {
+        return;
+    };
+    let mut totals: Vec<(NaiveDate, i64)> = day_totals(&purchases);
+    let (Some((first, _)), Some((last, _))) = (totals.first().copied(), totals.last().copied())
+    else {
+        println!("No purchases recorded yet");
+        return;
+    };
+    let zero: usize = totals.iter().filter(|(_, total)| *total == 0).count();
+    // sort is stable, so days with the same total stay in date order
+    totals.sort_by_key(|(_, total)| std::cmp::Reverse(*total));
+    println!("biggest days:");
+    for (date, total) in totals.iter().take(top) {
+        println!("  {}: {}", date, format_amount(*total, currency));
+    }
+    totals.sort_by_key(|(_, total)| *total);
+    println!("quietest days:");
+    for (date, total) in totals.iter().take(top) {
+        println!("  {}: {}", date, format_amount(*total, currency));
+    }
+    println!(
+        "{} of {} days from {} to {} without spending",
+        zero,
+        totals.len(),
+        first,
+        last
+    );
+}
+
+fn monthly_trend(totals: &BTreeMap<(i32, u32), i64>) -> Vec<((i32, u32), i64, Option<i64>)> {
+    //! Pair each month's total with its change from the month before
+    //! Months between the first and the last one without purchases are filled in as zero;
+    //! the first month has no change
+    let mut trend: Vec<((i32, u32), i64, Option<i64>)> = Vec::new();
+    let (Some(&first), Some(&last)) = (totals.keys().next(), totals.keys().next_back()) else {
+        return trend;
+    };
+    let mut month: (i32, u32) = first;
+    let mut previous: Option<i64> = None;
+    while month <= last {
+        let total: i64 = totals.get(&month).copied().unwrap_or(0);
+        trend.push((month, total, previous.map(|previous| total - previous)));
+        previous = Some(total);
+        month = match month {
+            (year, 12) => (year + 1, 1),
+            (year, month) => (year, month + 1),
+        };
+    }
+    trend
+}
+
+fn exec_trend(store: &Store, currency: &Currency) {
+    //! Print the total of each month with its change from the month before
+    let Some(purchases) = load_purchases(store) else {
+        return;
+    };
+    let trend: Vec<((i32, u32), i64, Option<i64>)> = monthly_trend(&monthly_totals(&purchases));
+    if trend.is_empty() {
+        println!("No purchases recorded yet");
+        return;
+    }
+    for ((year, month), total, change) in trend {
+        let change: String = match change {
+            None => String::from("—"),
+            Some(change) => {
+                let previous: i64 = total - change;
+                let arrow: &str = match change.cmp(&0) {
+                    Ordering::Greater => "↑",
+                    Ordering::Less => "↓",
+                    Ordering::Equal => "→",
+                };
+                let sign: &str = if change > 0 { "+" } else { "" };
+                let percent: String = if previous == 0 {
+                    String::from("n/a")
+                } else {
+                    format!("{}{:.1}%", sign, change as f64 / previous as f64 * 100.0)
+                };
+                format!(
+                    "{} {}{} ({})",
+                    arrow,
+                    sign,
+                    format_amount(change, currency),
+                    percent
+                )
+            }
+        };
+        println!(
+            "{}-{:02}: {} {}",
+            year,
+            month,
+            format_amount(total, currency),
+            change
+        );
+    }
+}
+
+fn monthly_category_totals(
+    purchases: &[Purchase],
+) -> BTreeMap<(i32, u32), HashMap<ProductType, i64>> {
+    //! Sum the value of purchases (in cents) per calendar month and category
+    let mut totals: BTreeMap<(i32, u32), HashMap<ProductType, i64>> = BTreeMap::new();
+    for purchase in purchases {
+        *totals
+            .entry((purchase.date.year(), purchase.date.month()))
+            .or_default()
+            .entry(purchase.product.product_type.clone())
+            .or_insert(0) += purchase.value();
+    }
+    totals
+}
+
+fn typical_month(purchases: &[Purchase], today: NaiveDate) -> (Vec<(ProductType, i64)>, usize) {
+    //! Average monthly spending (in cents) per category over the months before the month of today,
+    //! dividing each category's total by the number of those months with any purchases,
+    //! with that number of months
+    //! Categories are ordered by their average, highest first; the partial current month is left out
+    let current: (i32, u32) = (today.year(), today.month());
+    let complete: Vec<HashMap<ProductType, i64>> = monthly_category_totals(purchases)
+        .into_iter()
+        .filter(|(month, _)| *month < current)
+        .map(|(_, categories)| categories)
+        .collect();
+    let mut totals: HashMap<ProductType, i64> = HashMap::new();
+    for categories in &complete {
+        for (category, value) in categories {
+            *totals.entry(category.clone()).or_insert(0) += value;
+        }
+    }
+    let mut averages: Vec<(ProductType, i64)> = totals
+        .into_iter()
+        .map(|(category, total)| (category, rounding::round_div(total, complete.len() as i64)))
+        .collect();
+    averages.sort_by_key(|(category, average)| (std::cmp::Reverse(*average), category.to_string()));
+    (averages, complete.len())
+}
+
+fn exec_typical(store: &Store) {
+    //! Print the typical month per category as "category, amount" lines,
+    //! which can be used as a budgets file
+    let Some(purchases) = load_purchases(store) else {
+        return;
+    };
+    let (averages, months) = typical_month(&purchases, Local::now().date_naive());
+    if months == 0 {
+        println!("No complete month recorded yet");
+        return;
+    }
+    println!(
+        "# average spending per month over {} complete months, usable as {}",
+        months, DEFAULT_BUDGETS_FILE
+    );
+    for (category, average) in averages {
+        println!(
+            "{}, {}",
+            quote_field(&category.to_string()),
+            format_cents(average)
+        );
+    }
+}
+
+fn read_key_amounts(file_name: &str) -> Result<Option<Vec<(String, i64)>>, String> {
+    //! Read a small "key, amount" file such as the budgets file
+    //! Blank lines and lines starting with # are ignored
+    //! Returns None if the file does not exist
+    let contents: String = match std::fs::read_to_string(file_name) {
+        Ok(contents) => contents,
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(error) => return Err(format!("could not read {}: {}", file_name, error)),
+    };
+    let mut entries: Vec<(String, i64)> = Vec::new();
+    for (index, line) in contents.lines().enumerate() {
+        if line.trim().is_empty() || line.trim_start().starts_with('#') {
+            continue;
+        }
+        let entry: Option<(String, i64)> = match split_fields(line).as_deref() {
+            Some([key, amount]) => parse_cents(amount).map(|amount| (key.clone(), amount)),
+            _ => None,
+        };
+        match entry {
+            Some(entry) => entries.push(entry),
+            None => {
+                return Err(format!(
+                    "{} line {}: expected \"name, amount\"",
+                    file_name,
+                    index + 1
+                ))
+            }
+        }
+    }
+    Ok(Some(entries))
+}
+
+fn read_rates(file_name: &str) -> Result<HashMap<String, i64>, String> {
+    //! Read exchange rates from a "code, rate" file, where rate is the value of one unit
+    //! of the currency in the displayed one, with up to six decimals
+    //! Blank lines and lines starting with # are ignored; a missing file defines no rates
+    let contents: String = match std::fs::read_to_string(file_name) {
+        Ok(contents) => contents,
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => return Ok(HashMap::new()),
+        Err(error) => return Err(format!("could not read {}: {}", file_name, error)),
+    };
+    let mut rates: HashMap<String, i64> = HashMap::new();
+    for (index, line) in contents.lines().enumerate() {
+        if line.trim().is_empty() || line.trim_start().starts_with('#') {
+            continue;
+        }
+        let rate: Option<(String, i64)> = match split_fields(line).as_deref() {
+            Some([code, rate]) => parse_fixed(rate, 6)
+                .filter(|rate| *rate > 0)
+                .map(|rate| (code.to_ascii_uppercase(), rate)),
+            _ => None,
+        };
+        match rate {
+            Some((code, rate)) => {
+                rates.insert(code, rate);
+            }
+            None => {
+                return Err(format!(
+                    "{} line {}: expected \"currency code, positive rate\"",
+                    file_name,
+                    index + 1
+                ))
+            }
+        }
+    }
+    Ok(rates)
+}
+
+fn convert_currencies(purchases: &mut [Purchase], rates: &HashMap<String, i64>) -> Vec<String> {
+    //! Convert the prices in other currencies to the displayed one with the exchange rates
+    //! Purchases in a currency without a rate are left as they are;
+    //! returns those currencies in order of first appearance
+    let mut unknown: Vec<String> = Vec::new();
+    for purchase in purchases {
+        match convert_currency(purchase, rates) {
+            Some(code) if !unknown.contains(&code) => unknown.push(code),
+            _ => {}
+        }
+    }
+    unknown
+}
+
+fn convert_currency(purchase: &mut Purchase, rates: &HashMap<String, i64>) -> Option<String> {
+    //! Convert the price of one purchase as convert_currencies does,
+    //! returning its currency if there is no rate for it
+    let code: String = purchase.currency.clone()?;
+    let Some(rate) = rates.get(&code) else {
+        return Some(code);
+    };
+    purchase.product.price_cents =
+        rounding::round_div(purchase.product.price_cents * rate, 1_000_000);
+    purchase.currency = None;
+    None
+}
+
+fn read_aliases(file_name: &str) -> Result<HashMap<String, ProductType>, String> {
+    //! Read category aliases from an "alias, category" file
+    //! Blank lines and lines starting with # are ignored; a missing file defines no aliases
+    let contents: String = match std::fs::read_to_string(file_name) {
+        Ok(contents) => contents,
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => return Ok(HashMap::new()),
+        Err(error) => return Err(format!("could not read {}: {}", file_name, error)),
+    };
+    let mut aliases: HashMap<String, ProductType> = HashMap::new();
+    for (index, line) in contents.lines().enumerate() {
+        if line.trim().is_empty() || line.trim_start().starts_with('#') {
+            continue;
+        }
+        match split_fields(line).as_deref() {
+            Some([alias, category]) if !alias.is_empty() => {
+                aliases.insert(alias.to_lowercase(), ProductType::from_string(category));
+            }
+            _ => {
+                return Err(format!(
+                    "{} line {}: expected \"alias, category\"",
+                    file_name,
+                    index + 1
+                ))
+            }
+        }
+    }
+    Ok(aliases)
+}
+
+fn read_budgets(file_name: &str) -> Result<HashMap<ProductType, i64>, String> {
+    //! Read monthly budgets per category (in cents) from a "category, limit" file
+    //! A missing budgets file means no budgets are defined
+    Ok(read_key_amounts(file_name)?
+        .unwrap_or_default()
+        .into_iter()
+        .map(|(category, limit)| (ProductType::from_string(&category), limit))
+        .collect())
+}
+
+// a category's change between two months: (category, value before, value after)
+type CategoryChange = (ProductType, i64, i64);
+
+fn biggest_changes(
+    before: &HashMap<ProductType, i64>,
+    after: &HashMap<ProductType, i64>,
+) -> (Option<CategoryChange>, Option<CategoryChange>) {
+    //! Find the category that grew the most and the one that shrank the most between two months
+    //! Categories missing in a month count as zero there; ties go to the first category by name
+    let mut changes: Vec<CategoryChange> = before
+        .keys()
+        .chain(
+            after
+                .keys()
+                .filter(|category| !before.contains_key(category)),
+        )
+        .map(|category| {
+            (
+                category.clone(),
+                before.get(category).copied().unwrap_or(0),
+                after.get(category).copied().unwrap_or(0),
+            )
+        })
+        .collect();
+    changes.sort_by_key(|(category, _, _)| category.to_string());
+    let increase: Option<CategoryChange> = changes
+        .iter()
+        .filter(|(_, before, after)| after > before)
+        .min_by_key(|(_, before, after)| std::cmp::Reverse(after - before))
+        .cloned();
+    let decrease: Option<CategoryChange> = changes
+        .iter()
+        .filter(|(_, before, after)| after < before)
+        .min_by_key(|(_, before, after)| after - before)
+        .cloned();
+    (increase, decrease)
+}
+
+fn describe_change((category, before, after): &CategoryChange, currency: &Currency) -> String {
+    //! Describe a category change like "travel +$120.00 (up 80.0%)"
+    let change: i64 = after - before;
+    let sign: &str = if change > 0 { "+" } else { "" };
+    let percent: String = if *before == 0 {
+        String::from("new")
+    } else {
+        let direction: &str = if change > 0 { "up" } else { "down" };
+        format!(
+            "{} {:.1}%",
+            direction,
+            change.abs() as f64 / *before as f64 * 100.0
+        )
+    };
+    format!(
+        "{} {}{} ({})",
+        category,
+        sign,
+        format_amount(change, currency),
+        percent
+    )
+}
+
+fn exec_monthly(
+    store: &Store,
+    budgets: &HashMap<ProductType, i64>,
+    currency: &Currency,
+    out: &mut dyn Write,
+) -> std::io::Result<()> {
+    //! Print the total spending of each month in chronological order
+    //! If budgets are defined, each month is broken down per category
+    //! and categories are marked as over budget or with the budget remaining
+    let Some(purchases) = load_purchases(store) else {
+        return Ok(());
+    };
+    if purchases.is_empty() {
+        writeln!(out, "No purchases recorded yet")?;
+        return Ok(());
+    }
+    let by_category = monthly_category_totals(&purchases);
+    for ((year, month), total) in monthly_totals(&purchases) {
+        let line: String = format!("{}-{:02}: {}", year, month, format_amount(total, currency));
+        writeln!(out, "{}", color::bold(&line))?;
+        if budgets.is_empty() {
+            continue;
+        }
+        let mut categories: Vec<(&ProductType, &i64)> =
+            by_category[&(year, month)].iter().collect();
+        categories
+            .sort_by_key(|(category, value)| (std::cmp::Reverse(**value), category.to_string()));
+        for (category, value) in categories {
+            let line: String = format!("{}: {}", category, format_amount(*value, currency));
+            let line: String = match budgets.get(category) {
+                Some(limit) if value > limit => color::red(&format!(
+                    "{} OVER by {}",
+                    line,
+                    format_amount(value - limit, currency)
+                )),
+                Some(limit) => color::green(&format!(
+                    "{} remaining {}",
+                    line,
+                    format_amount(limit - value, currency)
+                )),
+                None => line,
+            };
+            writeln!(out, "  {}", line)?;
+        }
+    }
+    // highlight the biggest changes between the two most recent months
+    let mut recent = by_category.values().rev();
+    let (Some(last), Some(previous)) = (recent.next(), recent.next()) else {
+        writeln!(out, "Only one month recorded, no comparison is possible")?;
+        return Ok(());
+    };
+    let (increase, decrease) = biggest_changes(previous, last);
+    for (label, change) in [("increase", increase), ("decrease", decrease)] {
+        match change {
+            Some(change) => writeln!(
+                out,
+                "Biggest {}: {}",
+                label,
+                describe_change(&change, currency)
+            )?,
+            None => writeln!(out, "Biggest {}: none", label)?,
+        }
+    }
+    Ok(())
+}
+
+fn yearly_category_matrix(purchases: &[Purchase]) -> BTreeMap<i32, HashMap<ProductType, i64>> {
+    //! Sum the value of purchases (in cents) per year and category
+    let mut matrix: BTreeMap<i32, HashMap<ProductType, i64>> = BTreeMap::new();
+    for purchase in purchases {
+        *matrix
+            .entry(purchase.date.year())
+            .or_default()
+            .entry(purchase.product.product_type.clone())
+            .or_insert(0) += purchase.value();
+    }
+    matrix
+}
+
+fn exec_yearly(store: &Store, currency: &Currency) {
+    //! Print a table of spending with a row per year and a column per category
+    //! Categories are sorted by name; missing combinations show as zero
+    let Some(purchases) = load_purchases(store) else {
+        return;
+    };
+    let matrix: BTreeMap<i32, HashMap<ProductType, i64>> = yearly_category_matrix(&purchases);
+    if matrix.is_empty() {
+        println!("No purchases recorded yet");
+        return;
+    }
+    let mut categories: Vec<&ProductType> = matrix.values().flat_map(|row| row.keys()).collect();
+    categories.sort_by_key(|category| category.to_string());
+    categories.dedup();
+    // format every cell first so columns can be sized to fit
+    let rows: Vec<(i32, Vec<String>)> = matrix
+        .iter()
+        .map(|(year, row)| {
+            let cells: Vec<String> = categories
+                .iter()
+                .map(|category| format_amount(*row.get(*category).unwrap_or(&0), currency))
+                .collect();
+            (*year, cells)
+        })
+        .collect();
+    let widths: Vec<usize> = categories
+        .iter()
+        .enumerate()
+        .map(|(column, category)| {
+            rows.iter()
+                .map(|(_, cells)| cells[column].chars().count())
+                .chain([category.to_string().chars().count()])
+                .max()
+                .unwrap()
+        })
+        .collect();
+    let mut header: String = String::from("year");
+    for (category, width) in categories.iter().zip(&widths) {
+        header.push_str(&format!(
+            "  {:>width$}",
+            category.to_string(),
+            width = width
+        ));
+    }
+    println!("{}", header);
+    for (year, cells) in rows {
+        let mut line: String = format!("{:<4}", year);
+        for (cell, width) in cells.iter().zip(&widths) {
+            line.push_str(&format!("  {:>width$}", cell, width = width));
+        }
+        println!("{}", line);
+    }
+}
+
+fn tag_totals(purchases: &[Purchase]) -> BTreeMap<String, i64> {
+    //! Sum the value of purchases (in cents) per tag
+    //! A purchase with several tags counts towards each of them
+    let mut totals: BTreeMap<String, i64> = BTreeMap::new();
+    for purchase in purchases {
+        for tag in &purchase.tags {
+            *totals.entry(tag.clone()).or_insert(0) += purchase.value();
+        }
+    }
+    totals
+}
+
+fn exec_by_tag(store: &Store, tag: Option<&str>, currency: &Currency) {
+    //! Print the total spending per tag, highest first,
+    //! or only the total of the given tag
+    let Some(purchases) = load_purchases(store) else {
+        return;
+    };
+    let totals: BTreeMap<String, i64> = tag_totals(&purchases);
+    if let Some(tag) = tag {
+        let tag: String = normalize_tag(tag);
+        let total: i64 = totals.get(&tag).copied().unwrap_or(0);
+        println!("{}: {}", tag, format_amount(total, currency));
+        return;
+    }
+    if totals.is_empty() {
+        println!("No tagged purchases recorded");
+        return;
+    }
+    let mut totals: Vec<(String, i64)> = totals.into_iter().collect();
+    totals.sort_by_key(|(_, total)| std::cmp::Reverse(*total));
+    for (tag, total) in totals {
+        println!("{}: {}", tag, format_amount(total, currency));
+    }
+}
+
+// name, price, currency, quantity, date and refund of a purchase, see find_duplicates
+type DuplicateKey<'a> = (String, i64, Option<&'a str>, Quantity, NaiveDate, bool);
+
+fn find_duplicates(purchases: &[Purchase]) -> Vec<(usize, usize)> {
+    //! Find purchases with identical name, price (in the same currency), quantity and date
+    //! returning (first, later) index pairs, pairing each later copy with the first occurrence
+    //! Names are compared ignoring case; a refund never duplicates a purchase
+    let mut first_seen: HashMap<DuplicateKey, usize> = HashMap::new();
+    let mut duplicates: Vec<(usize, usize)> = Vec::new();
+    for (index, purchase) in purchases.iter().enumerate() {
+        let key = (
+            purchase.product.key(),
+            purchase.product.price_cents,
+            purchase.currency.as_deref(),
+            purchase.quantity,
+            purchase.date,
+            purchase.refund,
+        );
+        match first_seen.get(&key) {
+            Some(&first) => duplicates.push((first, index)),
+            None => {
+                first_seen.insert(key, index);
+            }
+        }
+    }
+    duplicates
+}
+
+fn purchase_problems(purchases: &[(String, Purchase)], today: NaiveDate) -> Vec<(usize, String)> {
+    //! Problems of purchases that could be read, given with where each purchase is found:
+    //! dates in the future and duplicates (see find_duplicates)
+    //! Each comes with the index of its purchase, in that order
+    let mut problems: Vec<(usize, String)> = purchases
+        .iter()
+        .enumerate()
+        .filter(|(_, (_, purchase))| purchase.date > today)
+        .map(|(index, (location, purchase))| {
+            let problem: String = format!("{}: date {} is in the future", location, purchase.date);
+            (index, problem)
+        })
+        .collect();
+    let read: Vec<Purchase> = purchases
+        .iter()
+        .map(|(_, purchase)| purchase.clone())
+        .collect();
+    for (first, later) in find_duplicates(&read) {
+        let problem: String = format!("{}: duplicates {}", purchases[later].0, purchases[first].0);
+        problems.push((later, problem));
+    }
+    // sorting is stable, so a purchase's problems stay in the order above
+    problems.sort_by_key(|(index, _)| *index);
+    problems
+}
+
+fn check_lines(reader: impl BufRead, today: NaiveDate) -> Result<Vec<String>, ReadError> {
+    //! Every problem of a text data file: lines that cannot be read as a purchase
+    //! (malformed lines, invalid prices, quantities or dates)
+    //! and the problems of the purchases read (see purchase_problems), in line order
+    let mut problems: Vec<(usize, String)> = Vec::new();
+    let mut purchases: Vec<(String, Purchase)> = Vec::new();
+    let mut line_numbers: Vec<usize> = Vec::new();
+    for (index, line) in reader.lines().enumerate() {
+        let line: String = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        match parse_line(&line, index + 1) {
+            Ok(purchase) => {
+                purchases.push((format!("line {}", index + 1), purchase));
+                line_numbers.push(index + 1);
+            }
+            Err(error) => problems.push((index + 1, error.to_string())),
+        }
+    }
+    for (index, problem) in purchase_problems(&purchases, today) {
+        problems.push((line_numbers[index], problem));
+    }
+    problems.sort_by_key(|(line, _)| *line);
+    Ok(problems.into_iter().map(|(_, problem)| problem).collect())
+}
+
+fn exec_check(store: &Store) {
+    //! Report every problem of the data files without changing them,
+    //! printing OK if there are none and exiting with 1 otherwise
+    //! Purchases of files that are not text files are located by their index
+    let today: NaiveDate = Local::now().date_naive();
+    let files: Vec<&String> = std::iter::once(&store.file_name)
+        .chain(&store.merged)
+        .collect();
+    let mut found: bool = false;
+    for file_name in &files {
+        let problems: Result<Vec<String>, ReadError> = match store.format {
+            Format::Text => open_data_file(file_name)
+                .map_err(ReadError::from)
+                .and_then(|file| check_lines(BufReader::new(file), today)),
+            _ => store.read_file(file_name).map(|purchases| {
+                let located: Vec<(String, Purchase)> = purchases
+                    .into_iter()
+                    .enumerate()
+                    .map(|(index, purchase)| (format!("purchase [{}]", index), purchase))
+                    .collect();
+                purchase_problems(&located, today)
+                    .into_iter()
+                    .map(|(_, problem)| problem)
+                    .collect()
+            }),
+        };
+        let problems: Vec<String> = match problems {
+            Ok(problems) => problems,
+            Err(error) => vec![error.to_string()],
+        };
+        for problem in &problems {
+            if files.len() > 1 {
+                println!("{}: {}", file_name, problem);
+            } else {
+                println!("{}", problem);
+            }
+        }
+        found |= !problems.is_empty();
+    }
+    if found {
+        std::process::exit(1);
+    }
+    println!("OK");
+}
+
+// products with fewer purchases have no reliable typical price
+const MIN_OUTLIER_SAMPLES: usize = 3;
+const DEFAULT_OUTLIER_FACTOR: f64 = 3.0;
+
+fn price_outliers(purchases: &[Purchase], factor: f64) -> Vec<(usize, f64)> {
+    //! Find purchases whose unit price is more than factor times above or below
+    //! the median unit price of their product, told apart by name ignoring case
+    //! Returns their indices in file order with the median (in cents);
+    //! products with fewer than MIN_OUTLIER_SAMPLES purchases or a median of zero are skipped
+    let mut prices: HashMap<String, Vec<i64>> = HashMap::new();
+    for purchase in purchases {
+        prices
+            .entry(purchase.product.key())
+            .or_default()
+            .push(purchase.product.price_cents);
+    }
+    let medians: HashMap<String, f64> = prices
+        .into_iter()
+        .filter(|(_, prices)| prices.len() >= MIN_OUTLIER_SAMPLES)
+        .filter_map(|(key, prices)| Some((key, stats::median(&prices)?)))
+        .filter(|(_, median)| *median > 0.0)
+        .collect();
+    purchases
+        .iter()
+        .enumerate()
+        .filter_map(|(index, purchase)| {
+            let median: f64 = *medians.get(&purchase.product.key())?;
+            let price: f64 = purchase.product.price_cents as f64;
+            (price > median * factor || price * factor < median).then_some((index, median))
+        })
+        .collect()
+}
+
+fn exec_outliers(store: &Store, factor: f64, currency: &Currency) {
+    //! List the purchases whose price is far from the usual price of their product,
+    //! with their indices for edit or delete
+    let Some(purchases) = load_purchases(store) else {
+        return;
+    };
+    let outliers: Vec<(usize, f64)> = price_outliers(&purchases, factor);
+    if outliers.is_empty() {
+        println!("No price outliers found");
+        return;
+    }
+    for (index, median) in outliers {
+        println!(
+            "[{}] {} (usually {})",
+            index,
+            purchase_to_line(&purchases[index]),
+            format_amount(rounding::round_cents(median), currency)
+        );
+    }
+}
+
+fn cli_dedupe(store: &Store) {
+    //! List duplicate purchases and offer to remove the later copy of each
+    //! The file is left untouched unless the user confirms
+    let Some(purchases) = load_purchases(store) else {
+        return;
+    };
+    let duplicates: Vec<(usize, usize)> = find_duplicates(&purchases);
+    if duplicates.is_empty() {
+        println!("No duplicates found");
+        return;
+    }
+    for (first, later) in &duplicates {
+        println!(
+            "[{}] duplicates [{}]: {}",
+            later,
+            first,
+            purchase_to_line(&purchases[*later])
+        );
+    }
+    let answer: String = prompt(
+        &format!("Remove the {} later copies? (y/n)", duplicates.len()),
+        None,
+    );
+    if answer != "y" {
+        println!("No purchases removed");
+        return;
+    }
+    // look for duplicates again, the file may have changed while the user was asked
+    let removed = store.modify(|purchases| {
+        let later: Vec<usize> = find_duplicates(purchases)
+            .into_iter()
+            .map(|(_, later)| later)
+            .collect();
+        let mut index: usize = 0;
+        purchases.retain(|_| {
+            index += 1;
+            !later.contains(&(index - 1))
+        });
+        Ok::<usize, ReadError>(later.len())
+    });
+    match removed {
+        Ok(removed) => println!("Removed {} duplicates", removed),
+        Err(error) => println!("Could not rewrite file: {}", error),
+    }
+}
+
+fn frequent_products(purchases: &[Purchase], by_value: bool) -> Vec<(String, Quantity, i64)> {
+    //! Sum the quantity and value (in cents) of each product, told apart by name ignoring case
+    //! and shown as first written, ranked by quantity (or by value) with the highest first
+    //! Ties are broken alphabetically
+    let mut totals: Vec<(String, String, Quantity, i64)> = Vec::new();
+    for purchase in purchases {
+        let key: String = purchase.product.key();
+        match totals.iter_mut().find(|(other, _, _, _)| *other == key) {
+            Some((_, _, quantity, value)) => {
+                *quantity = *quantity + purchase.quantity;
+                *value += purchase.value();
+            }
+            None => totals.push((
+                key,
+                purchase.product.name.clone(),
+                purchase.quantity,
+                purchase.value(),
+            )),
+        }
+    }
+    totals.sort_by(
+        |(a_key, _, a_quantity, a_value), (b_key, _, b_quantity, b_value)| {
+            let ranking: Ordering = if by_value {
+                b_value.cmp(a_value)
+            } else {
+                b_quantity.cmp(a_quantity)
+            };
+            ranking.then_with(|| a_key.cmp(b_key))
+        },
+    );
+    totals
+        .into_iter()
+        .map(|(_, name, quantity, value)| (name, quantity, value))
+        .collect()
+}
+
+fn exec_frequent(store: &Store, top: usize, by_value: bool, currency: &Currency) {
+    //! Print the top products bought most often (or spent most on) with their quantity and value
+    let Some(purchases) = load_purchases(store) else {
+        return;
+    };
+    if purchases.is_empty() {
+        println!("No purchases recorded yet");
+        return;
+    }
+    for (name, quantity, value) in frequent_products(&purchases, by_value)
+        .into_iter()
+        .take(top)
+    {
+        println!(
+            "{}: {} bought, {}",
+            name,
+            quantity,
+            format_amount(value, currency)
+        );
+    }
+}
+
+fn exec_drilldown(store: &Store, category: &ProductType, currency: &Currency) {
+    //! Print the products of a category with their value and quantity, highest value first,
+    //! followed by the total of the category
+    let Some(purchases) = load_purchases(store) else {
+        return;
+    };
+    let in_category: Vec<Purchase> = or_exit(filter_by_category(&purchases, category))
+        .into_iter()
+        .cloned()
+        .collect();
+    if in_category.is_empty() {
+        println!("No purchases of category {}", category);
+        return;
+    }
+    for (name, quantity, value) in frequent_products(&in_category, true) {
+        println!(
+            "{}: {}, {} bought",
+            name,
+            format_amount(value, currency),
+            quantity
+        );
+    }
+    let total: i64 = in_category.iter().map(Purchase::value).sum();
+    println!("Total {}: {}", category, format_amount(total, currency));
+}
+
+fn rename_category(purchases: &mut [Purchase], old: &ProductType, new: &ProductType) -> usize {
+    //! Change the category of every purchase of category old to new
+    //! returning the number of purchases changed
+    let mut changed: usize = 0;
+    for purchase in purchases
+        .iter_mut()
+        .filter(|purchase| purchase.product.product_type == *old)
+    {
+        purchase.product.product_type = new.clone();
+        changed += 1;
+    }
+    changed
+}
+
+fn exec_rename_category(store: &Store, old: &str, new: &str) {
+    //! Rename a category across all purchases, rewriting the data file
+    //! A new name close to a built-in category is refused as a probable typo
+    let Some(purchases) = load_purchases(store) else {
+        return;
+    };
+    if new.trim().is_empty() {
+        eprintln!("the new category name must not be empty");
+        std::process::exit(1);
+    }
+    let (old, new): (ProductType, ProductType) =
+        (ProductType::from_string(old), ProductType::from_string(new));
+    if !known_categories(&purchases).contains(&new.to_string()) {
+        if let Some(category) = suggest_category(&new.to_string()) {
+            eprintln!(
+                "'{}' is not a category yet, did you mean {}? Category not renamed",
+                new, category
+            );
+            std::process::exit(1);
+        }
+    }
+    let changed =
+        store.modify(|purchases| Ok::<usize, ReadError>(rename_category(purchases, &old, &new)));
+    match changed {
+        Ok(0) => println!("No purchases of category {}", old),
+        Ok(changed) => println!("Renamed {} to {} in {} purchases", old, new, changed),
+        Err(error) => println!("Could not rewrite file: {}", error),
+    }
+}
+
+fn archive_purchases(
+    store: &Store,
+    archive: &Store,
+    before: NaiveDate,
+) -> Result<(usize, usize), ReadError> {
+    //! Move the purchases dated before the cutoff to the end of the archive,
+    //! returning how many were archived and how many were retained
+    //! The archive is written completely before the data file is rewritten,
+    //! so an interruption can leave purchases in both files but never in neither
+    store.modify(|purchases| {
+        let (archived, retained): (Vec<Purchase>, Vec<Purchase>) = std::mem::take(purchases)
+            .into_iter()
+            .partition(|purchase| purchase.date < before);
+        *purchases = retained;
+        let count: usize = archived.len();
+        if count > 0 {
+            archive.modify(|archive_purchases| {
+                archive_purchases.extend(archived);
+                Ok::<(), ReadError>(())
+            })?;
+        }
+        Ok((count, purchases.len()))
+    })
+}
+
+fn exec_archive(store: &Store, archive: &str, before: NaiveDate) {
+    //! Move the purchases dated before the cutoff into the archive file,
+    //! kept in the format of the data file
+    let archive = Store {
+        file_name: archive.to_string(),
+        format: store.format,
+        merged: Vec::new(),
+    };
+    match archive_purchases(store, &archive, before) {
+        Ok((0, _)) => println!("No purchases dated before {}", before),
+        Ok((archived, retained)) => println!(
+            "Archived {} purchases to {}, {} retained",
+            archived, archive.file_name, retained
+        ),
+        Err(error) => {
+            eprintln!("Could not archive purchases: {}", error);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn search_purchases<'a>(purchases: &'a [Purchase], query: &str) -> Vec<&'a Purchase> {
+    //! Find purchases whose product name contains the query (case-insensitive)
+    //! An empty query matches nothing
+    let query: String = query.trim().to_lowercase();
+    if query.is_empty() {
+        return Vec::new();
+    }
+    purchases
+        .iter()
+        .filter(|purchase| purchase.product.key().contains(&query))
+        .collect()
+}
+
+fn in_price_range(purchase: &Purchase, min: Option<i64>, max: Option<i64>) -> bool {
+    //! Check whether the unit price of a purchase lies within the inclusive range min..=max
+    let price_cents: i64 = purchase.product.price_cents;
+    min.is_none_or(|min| price_cents >= min) && max.is_none_or(|max| price_cents <= max)
+}
+
+fn filter_by_price<'a>(
+    purchases: impl IntoIterator<Item = &'a Purchase>,
+    min: Option<i64>,
+    max: Option<i64>,
+) -> Vec<&'a Purchase> {
+    //! Filter purchases by unit price (in cents)
+    //! keeping those within the inclusive range min..=max
+    //! A missing bound leaves that side of the range open
+    purchases
+        .into_iter()
+        .filter(|purchase| in_price_range(purchase, min, max))
+        .collect()
+}
+
+fn exec_search(
+    store: &Store,
+    query: &str,
+    min_price: Option<i64>,
+    max_price: Option<i64>,
+    currency: &Currency,
+) {
+    //! Print every purchase matching the query with its date, quantity and value
+    //! followed by the number of matches and their total value
+    //! If a price bound is given, only matches within the price range are shown,
+    //! most expensive first
+    let Some(purchases) = load_purchases(store) else {
+        return;
+    };
+    let mut matches: Vec<&Purchase> = search_purchases(&purchases, query);
+    if min_price.is_some() || max_price.is_some() {
+        matches = filter_by_price(matches, min_price, max_price);
+        matches.sort_by_key(|purchase| std::cmp::Reverse(purchase.product.price_cents));
+    }
+    for purchase in &matches {
+        println!(
+            "{} {} x{}: {}{}",
+            format_date(purchase.date),
+            purchase.product.name,
+            purchase.signed_quantity(),
+            format_amount(purchase.value(), currency),
+            note_suffix(purchase)
+        );
+    }
+    let total: i64 = matches.iter().map(|purchase| purchase.value()).sum();
+    println!(
+        "{} matches, total: {}",
+        matches.len(),
+        format_amount(total, currency)
+    );
+}
+
+fn filter_by_category<'a>(
+    purchases: &'a [Purchase],
+    category: &ProductType,
+) -> Result<Vec<&'a Purchase>, String> {
+    //! Keep only the purchases of the given category
+    //! A custom category that no purchase has is most likely a typo,
+    //! so it is an error listing the known categories
+    let filtered: Vec<&Purchase> = purchases
+        .iter()
+        .filter(|purchase| purchase.product.product_type == *category)
+        .collect();
+    if filtered.is_empty() && matches!(category, ProductType::Custom(_)) {
+        return Err(format!(
+            "unknown category '{}', expected one of: {}",
+            category,
+            known_categories(purchases).join(", ")
+        ));
+    }
+    Ok(filtered)
+}
+
+fn what_if(purchases: &[Purchase], excluded: &[ProductType]) -> (i64, i64) {
+    //! Total spending (in cents) and how much of it falls into the excluded categories
+    let purchases: Vec<&Purchase> = purchases.iter().collect();
+    let buckets: Vec<Bucket> = sort_type_buckets(&purchases);
+    let total: i64 = buckets.iter().map(eval_bucket_value).sum();
+    let saved: i64 = buckets
+        .iter()
+        .filter(|bucket| excluded.contains(&bucket.product_type))
+        .map(eval_bucket_value)
+        .sum();
+    (total, saved)
+}
+
+fn exec_what_if(store: &Store, excluded: &[ProductType], currency: &Currency) {
+    //! Print how much less would have been spent without the excluded categories
+    let Some(purchases) = load_purchases(store) else {
+        return;
+    };
+    for category in excluded {
+        or_exit(filter_by_category(&purchases, category));
+    }
+    let (total, saved) = what_if(&purchases, excluded);
+    let mut names: Vec<String> = excluded.iter().map(ProductType::to_string).collect();
+    let last: String = names.pop().unwrap_or_default();
+    let names: String = if names.is_empty() {
+        last
+    } else {
+        format!("{} and {}", names.join(", "), last)
+    };
+    let reduction: String = if total == 0 {
+        String::new()
+    } else {
+        format!(" ({:.1}% reduction)", saved as f64 / total as f64 * 100.0)
+    };
+    println!(
+        "Excluding {} you'd have spent {} less{}: {} instead of {}",
+        names,
+        format_amount(saved, currency),
+        reduction,
+        format_amount(total - saved, currency),
+        format_amount(total, currency)
+    );
+}
+
+fn known_categories(purchases: &[Purchase]) -> Vec<String> {
+    //! Names of the built-in categories followed by the custom categories
+    //! of the purchases, in order of first appearance
+    let mut names: Vec<String> = ProductType::all_variants()
+        .iter()
+        .map(ProductType::to_string)
+        .collect();
+    for purchase in purchases {
+        let name: String = purchase.product.product_type.to_string();
+        if !names.contains(&name) {
+            names.push(name);
+        }
+    }
+    names
+}
+
+fn month_progress(purchases: &[Purchase], today: NaiveDate) -> (i64, u32, u32) {
+    //! Spending (in cents) in the month of today up to and including today,
+    //! with the number of days elapsed and the number of days in the month
+    let first: NaiveDate = today.with_day(1).unwrap();
+    let next: NaiveDate = first.checked_add_months(Months::new(1)).unwrap();
+    let days: u32 = (next - first).num_days() as u32;
+    let spent: i64 = purchases
+        .iter()
+        .filter(|purchase| purchase.date >= first && purchase.date <= today)
+        .map(Purchase::value)
+        .sum();
+    (spent, today.day(), days)
+}
+
+// how much faster than usual (in percent) spending may be before velocity warns
+const DEFAULT_VELOCITY_THRESHOLD: f64 = 20.0;
+
+fn spending_velocity(purchases: &[Purchase], today: NaiveDate) -> Option<(f64, f64)> {
+    //! Average spending per day (in cents) in the month of today up to and including today,
+    //! and over the complete months from the first purchase to the end of the previous month
+    //! (months in between without purchases count as zero)
+    //! None if no purchase was made before the month of today
+    let first_of_month: NaiveDate = today.with_day(1).unwrap();
+    let first: NaiveDate = purchases
+        .iter()
+        .map(|purchase| purchase.date)
+        .filter(|date| *date < first_of_month)
+        .min()?
+        .with_day(1)
+        .unwrap();
+    let before: i64 = purchases
+        .iter()
+        .filter(|purchase| purchase.date >= first && purchase.date < first_of_month)
+        .map(Purchase::value)
+        .sum();
+    let days: i64 = (first_of_month - first).num_days();
+    let (spent, elapsed, _) = month_progress(purchases, today);
+    Some((spent as f64 / elapsed as f64, before as f64 / days as f64))
+}
+
+fn exec_velocity(store: &Store, threshold: f64, currency: &Currency) {
+    //! Compare the daily spending of the current month so far with that of the months before,
+    //! warning if it is more than threshold percent higher
+    let Some(purchases) = load_purchases(store) else {
+        return;
+    };
+    let Some((current, usual)) = spending_velocity(&purchases, Local::now().date_naive()) else {
+        println!("Not enough data: velocity needs at least one complete month before this one");
+        return;
+    };
+    let amount = |cents: f64| format_amount(rounding::round_cents(cents), currency);
+    println!("this month: {} per day", amount(current));
+    println!("previous months: {} per day", amount(usual));
+    if current > usual * (1.0 + threshold / 100.0) {
+        let faster: String = if usual > 0.0 {
+            format!("{:.1}% faster than", (current / usual - 1.0) * 100.0)
+        } else {
+            String::from("faster than")
+        };
+        println!(
+            "{}",
+            color::red(&format!("Warning: you are spending {} usual", faster))
+        );
+    } else {
+        println!(
+            "{}",
+            color::green(&format!(
+                "Spending is within {}% of the usual pace",
+                threshold
+            ))
+        );
+    }
+}
+
+fn exec_target(store: &Store, file_name: &str, amount: Option<&str>, currency: &Currency) {
+    //! Set the monthly spending target if an amount is given,
+    //! otherwise show how the current month is tracking against it
+    //! The month's spending is projected linearly from the days elapsed
+    if let Some(amount) = amount {
+        let target: i64 = match parse_cents(amount).map(check_price) {
+            Some(Ok(target)) => target,
+            _ => {
+                eprintln!("target must be a non-negative amount like 500.00");
+                std::process::exit(1);
+            }
+        };
+        if let Err(error) = std::fs::write(file_name, format_cents(target)) {
+            eprintln!("Could not write {}: {}", file_name, error);
+            std::process::exit(1);
+        }
+        println!("Monthly target set to {}", format_amount(target, currency));
+        return;
+    }
+    let target: i64 = match std::fs::read_to_string(file_name) {
+        Ok(contents) => match parse_cents(contents.trim()) {
+            Some(target) => target,
+            None => {
+                eprintln!("{}: '{}' is not an amount", file_name, contents.trim());
+                std::process::exit(1);
+            }
+        },
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => {
+            println!("No monthly target set yet, set one with: fima target AMOUNT");
+            return;
+        }
+        Err(error) => {
+            eprintln!("Could not read {}: {}", file_name, error);
+            std::process::exit(1);
+        }
+    };
+    let Some(purchases) = load_purchases(store) else {
+        return;
+    };
+    let (spent, elapsed, days) = month_progress(&purchases, Local::now().date_naive());
+    let projected: i64 = rounding::round_cents(spent as f64 / elapsed as f64 * days as f64);
+    println!("target: {} per month", format_amount(target, currency));
+    println!("day {} of {}", elapsed, days);
+    println!("spent so far: {}", format_amount(spent, currency));
+    if projected > target {
+        println!(
+            "projected: {}, projected over budget by {}",
+            format_amount(projected, currency),
+            format_amount(projected - target, currency)
+        );
+    } else {
+        println!(
+            "projected: {}, on pace with {} to spare",
+            format_amount(projected, currency),
+            format_amount(target - projected, currency)
+        );
+    }
+}
+
+fn year_projection(
+    purchases: &[Purchase],
+    today: NaiveDate,
+) -> (Vec<(ProductType, i64, i64)>, u32, u32) {
+    //! Spending (in cents) per category in the year of today up to and including today,
+    //! each with its projection to the whole year, highest first,
+    //! and the number of days elapsed and the number of days in the year
+    //! Today counts as a whole day, so there always is at least one day to scale from
+    let first: NaiveDate = today.with_ordinal(1).unwrap();
+    let days: u32 = if today.leap_year() { 366 } else { 365 };
+    let elapsed: u32 = today.ordinal();
+    let in_year: Vec<&Purchase> = purchases
+        .iter()
+        .filter(|purchase| purchase.date >= first && purchase.date <= today)
+        .collect();
+    let mut projections: Vec<(ProductType, i64, i64)> = sort_type_buckets(&in_year)
+        .iter()
+        .map(|bucket| {
+            let spent: i64 = eval_bucket_value(bucket);
+            let projected: i64 = rounding::round_div(spent * days as i64, elapsed as i64);
+            (bucket.product_type.clone(), spent, projected)
+        })
+        .collect();
+    projections.sort_by_key(|(_, spent, _)| std::cmp::Reverse(*spent));
+    (projections, elapsed, days)
+}
+
+fn exec_projected(store: &Store, currency: &Currency) {
+    //! Print the spending per category so far this year projected to the whole year
+    let Some(purchases) = load_purchases(store) else {
+        return;
+    };
+    let today: NaiveDate = Local::now().date_naive();
+    let (projections, elapsed, days) = year_projection(&purchases, today);
+    if projections.is_empty() {
+        println!("No purchases recorded in {} yet", today.year());
+        return;
+    }
+    println!(
+        "{}, day {} of {}: spent so far, projected for the year",
+        today.year(),
+        elapsed,
+        days
+    );
+    for (category, spent, projected) in &projections {
+        println!(
+            "  {}: {}, {}",
+            category,
+            format_amount(*spent, currency),
+            format_amount(*projected, currency)
+        );
+    }
+    let spent: i64 = projections.iter().map(|(_, spent, _)| spent).sum();
+    let projected: i64 = projections.iter().map(|(_, _, projected)| projected).sum();
+    println!(
+        "Total: {}, {}",
+        format_amount(spent, currency),
+        format_amount(projected, currency)
+    );
+    println!(
+        "Projections assume spending continues at the same daily rate for the rest of the year, \
+         which is unreliable early in the year and for irregular expenses"
+    );
+}
+
+// an entry of the income file
+#[derive(Debug, Clone, PartialEq)]
+struct Income {
+    date: NaiveDate,
+    amount_cents: i64,
+    source: String,
+}
+
+impl Income {
+    fn to_line(&self) -> String {
+        //! Line of the income file for this entry, without the source if it is empty
+        let line: String = format!("{}, {}", self.date, format_cents(self.amount_cents));
+        if self.source.is_empty() {
+            line
+        } else {
+            format!("{}, {}", line, quote_field(&self.source))
+        }
+    }
+}
+
+fn read_income(file_name: &str) -> Result<Vec<Income>, String> {
+    //! Read the "date, amount[, source]" lines of the income file
+    //! Blank lines and lines starting with # are ignored; a missing file records no income
+    let contents: String = match std::fs::read_to_string(file_name) {
+        Ok(contents) => contents,
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(error) => return Err(format!("could not read {}: {}", file_name, error)),
+    };
+    let mut income: Vec<Income> = Vec::new();
+    for (index, line) in contents.lines().enumerate() {
+        if line.trim().is_empty() || line.trim_start().starts_with('#') {
+            continue;
+        }
+        let fields: Option<Vec<String>> = split_fields(line);
+        let entry: Option<Income> = match fields.as_deref() {
+            Some([date, amount, source @ ..]) if source.len() <= 1 => {
+                NaiveDate::parse_from_str(date, "%Y-%m-%d")
+                    .ok()
+                    .zip(parse_cents(amount))
+                    .map(|(date, amount_cents)| Income {
+                        date,
+                        amount_cents,
+                        source: source.first().cloned().unwrap_or_default(),
+                    })
+            }
+            _ => None,
+        };
+        match entry {
+            Some(entry) => income.push(entry),
+            None => {
+                return Err(format!(
+                    "{} line {}: expected \"yyyy-mm-dd, amount, source\"",
+                    file_name,
+                    index + 1
+                ))
+            }
+        }
+    }
+    Ok(income)
+}
+
+fn monthly_balance(purchases: &[Purchase], income: &[Income]) -> Vec<((i32, u32), i64, i64, i64)> {
+    //! Income, spending and the balance at the end of each month
+    //! with income or purchases, in chronological order
+    //! The balance is all income minus all spending up to the end of the month
+    let spent: BTreeMap<(i32, u32), i64> = monthly_totals(purchases);
+    let mut earned: BTreeMap<(i32, u32), i64> = BTreeMap::new();
+    for entry in income {
+        *earned
+            .entry((entry.date.year(), entry.date.month()))
+            .or_insert(0) += entry.amount_cents;
+    }
+    let mut months: Vec<(i32, u32)> = spent.keys().chain(earned.keys()).copied().collect();
+    months.sort();
+    months.dedup();
+    let mut balance: i64 = 0;
+    months
+        .into_iter()
+        .map(|month| {
+            let earned: i64 = earned.get(&month).copied().unwrap_or(0);
+            let spent: i64 = spent.get(&month).copied().unwrap_or(0);
+            balance += earned - spent;
+            (month, earned, spent, balance)
+        })
+        .collect()
+}
+
+fn exec_income(file_name: &str, amount: Option<&str>, source: &str, currency: &Currency) {
+    //! Record income of the amount dated today if one is given,
+    //! otherwise list the recorded income with its total
+    if let Some(amount) = amount {
+        let amount_cents: i64 = match parse_cents(amount).map(check_price) {
+            Some(Ok(amount_cents)) => amount_cents,
+            _ => {
+                eprintln!("income must be a non-negative amount like 1500.00");
+                std::process::exit(1);
+            }
+        };
+        let entry = Income {
+            date: Local::now().date_naive(),
+            amount_cents,
+            source: source.trim().to_string(),
+        };
+        let written: std::io::Result<()> = lock_file(file_name)
+            .and_then(|_lock| append_line(file_name, &entry.to_line()).map(|_| ()));
+        if let Err(error) = written {
+            eprintln!("Could not write {}: {}", file_name, error);
+            std::process::exit(1);
+        }
+        println!(
+            "Recorded income of {} on {}",
+            format_amount(amount_cents, currency),
+            entry.date
+        );
+        return;
+    }
+    let income: Vec<Income> = or_exit(read_income(file_name));
+    if income.is_empty() {
+        println!("No income recorded yet, record some with: fima income AMOUNT [SOURCE]");
+        return;
+    }
+    for entry in &income {
+        println!(
+            "{} {} {}",
+            entry.date,
+            format_amount(entry.amount_cents, currency),
+            entry.source
+        );
+    }
+    let total: i64 = income.iter().map(|entry| entry.amount_cents).sum();
+    println!("Total: {}", format_amount(total, currency));
+}
+
+fn exec_balance(store: &Store, income_file: &str, monthly: bool, currency: &Currency) {
+    //! Print the balance of all income minus all spending,
+    //! and with monthly the income, spending and balance of each month before it
+    let income: Vec<Income> = or_exit(read_income(income_file));
+    let Some(purchases) = load_purchases(store) else {
+        return;
+    };
+    let earned: i64 = income.iter().map(|entry| entry.amount_cents).sum();
+    let spent: i64 = purchases.iter().map(Purchase::value).sum();
+    if monthly {
+        for ((year, month), earned, spent, balance) in monthly_balance(&purchases, &income) {
+            println!(
+                "{}-{:02}: income {}, spent {}, net {}, balance {}",
+                year,
+                month,
+                format_amount(earned, currency),
+                format_amount(spent, currency),
+                format_amount(earned - spent, currency),
+                format_amount(balance, currency)
+            );
+        }
+    }
+    println!("income: {}", format_amount(earned, currency));
+    println!("spent: {}", format_amount(spent, currency));
+    println!("balance: {}", format_amount(earned - spent, currency));
+}
+
+// a line of the goals file: spend at most cap from from to to inclusive
+#[derive(Debug, Clone, PartialEq)]
+struct Goal {
+    name: String,
+    from: NaiveDate,
+    to: NaiveDate,
+    cap_cents: i64,
+}
+
+impl Goal {
+    fn to_line(&self) -> String {
+        //! Line of the goals file for this goal
+        format!(
+            "{}, {}, {}, {}",
+            quote_field(&self.name),
+            self.from,
+            self.to,
+            format_cents(self.cap_cents)
+        )
+    }
+}
+
+fn read_goals(file_name: &str) -> Result<Vec<Goal>, String> {
+    //! Read the "name, from, to, cap" lines of the goals file
+    //! Blank lines and lines starting with # are ignored; a missing file sets no goals
+    let contents: String = match std::fs::read_to_string(file_name) {
+        Ok(contents) => contents,
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(error) => return Err(format!("could not read {}: {}", file_name, error)),
+    };
+    let mut goals: Vec<Goal> = Vec::new();
+    for (index, line) in contents.lines().enumerate() {
+        if line.trim().is_empty() || line.trim_start().starts_with('#') {
+            continue;
+        }
+        let date = |date: &str| NaiveDate::parse_from_str(date, "%Y-%m-%d").ok();
+        let goal: Option<Goal> = match split_fields(line).as_deref() {
+            Some([name, from, to, cap]) => match (date(from), date(to), parse_cents(cap)) {
+                (Some(from), Some(to), Some(cap_cents)) if from <= to => Some(Goal {
+                    name: name.clone(),
+                    from,
+                    to,
+                    cap_cents,
+                }),
+                _ => None,
+            },
+            _ => None,
+        };
+        match goal {
+            Some(goal) => goals.push(goal),
+            None => {
+                return Err(format!(
+                    "{} line {}: expected \"name, yyyy-mm-dd, yyyy-mm-dd, cap\"",
+                    file_name,
+                    index + 1
+                ))
+            }
+        }
+    }
+    Ok(goals)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum GoalStatus {
+    // the period has not started yet
+    Upcoming,
+    // spending so far, projected linearly to the end of the period, stays within the cap
+    OnTrack,
+    OffTrack,
+    // the period has ended
+    Met,
+    Missed,
+}
+
+fn goal_progress(goal: &Goal, purchases: &[Purchase], today: NaiveDate) -> (i64, GoalStatus) {
+    //! Spending (in cents) within the period of the goal up to and including today,
+    //! and how the goal stands on today
+    let spent: i64 = purchases
+        .iter()
+        .filter(|purchase| purchase.date >= goal.from && purchase.date <= goal.to.min(today))
+        .map(Purchase::value)
+        .sum();
+    let status: GoalStatus = if today < goal.from {
+        GoalStatus::Upcoming
+    } else if today > goal.to {
+        if spent <= goal.cap_cents {
+            GoalStatus::Met
+        } else {
+            GoalStatus::Missed
+        }
+    } else {
+        let elapsed: i64 = (today - goal.from).num_days() + 1;
+        let days: i64 = (goal.to - goal.from).num_days() + 1;
+        if spent as f64 / elapsed as f64 * days as f64 <= goal.cap_cents as f64 {
+            GoalStatus::OnTrack
+        } else {
+            GoalStatus::OffTrack
+        }
+    };
+    (spent, status)
+}
+
+fn exec_goal(store: &Store, file_name: &str, new_goal: Option<Goal>, currency: &Currency) {
+    //! Add the new goal to the goals file if one is given,
+    //! otherwise show the progress of every goal
+    if let Some(goal) = new_goal {
+        // hold the lock from checking the names until the goal is written,
+        // so two goals of the same name cannot both be added
+        let _lock: File = or_exit(
+            lock_file(file_name)
+                .map_err(|error| format!("could not lock {}: {}", file_name, error)),
+        );
+        let goals: Vec<Goal> = or_exit(read_goals(file_name));
+        if goals.iter().any(|existing| existing.name == goal.name) {
+            eprintln!("a goal named '{}' already exists", goal.name);
+            std::process::exit(1);
+        }
+        if let Err(error) = append_line(file_name, &goal.to_line()) {
+            eprintln!("Could not write {}: {}", file_name, error);
+            std::process::exit(1);
+        }
+        println!(
+            "Goal '{}' set: spend at most {} from {} to {}",
+            goal.name,
+            format_amount(goal.cap_cents, currency),
+            goal.from,
+            goal.to
+        );
+        return;
+    }
+    let goals: Vec<Goal> = or_exit(read_goals(file_name));
+    if goals.is_empty() {
+        println!("No goals set yet, set one with: fima goal NAME CAP");
+        return;
+    }
+    let Some(purchases) = load_purchases(store) else {
+        return;
+    };
+    let today: NaiveDate = Local::now().date_naive();
+    for goal in &goals {
+        let (spent, status) = goal_progress(goal, &purchases, today);
+        let status: String = match status {
+            GoalStatus::Upcoming => String::from("not started"),
+            GoalStatus::OnTrack | GoalStatus::OffTrack => {
+                let left: i64 = (goal.to - today).num_days();
+                let track: String = if status == GoalStatus::OnTrack {
+                    color::green("on track")
+                } else {
+                    color::red("off track")
+                };
+                format!("{} days left, {}", left, track)
+            }
+            GoalStatus::Met => color::green("met"),
+            GoalStatus::Missed => color::red("missed"),
+        };
+        println!(
+            "{} ({} to {}): spent {} of {}, {}",
+            goal.name,
+            goal.from,
+            goal.to,
+            format_amount(spent, currency),
+            format_amount(goal.cap_cents, currency),
+            status
+        );
+    }
+}
+
+fn compare_periods(
+    purchases: &[Purchase],
+    a: (NaiveDate, NaiveDate),
+    b: (NaiveDate, NaiveDate),
+) -> Vec<(ProductType, i64, i64)> {
+    //! Sum the value of purchases (in cents) per category within each of two inclusive
+    //! date ranges, sorted by category name
+    //! A category found in only one period has a value of 0 in the other
+    let mut totals: HashMap<ProductType, (i64, i64)> = HashMap::new();
+    for purchase in filter_by_date_range(purchases, Some(a.0), Some(a.1)) {
+        totals
+            .entry(purchase.product.product_type.clone())
+            .or_default()
+            .0 += purchase.value();
+    }
+    for purchase in filter_by_date_range(purchases, Some(b.0), Some(b.1)) {
+        totals
+            .entry(purchase.product.product_type.clone())
+            .or_default()
+            .1 += purchase.value();
+    }
+    let mut rows: Vec<(ProductType, i64, i64)> = totals
+        .into_iter()
+        .map(|(category, (a, b))| (category, a, b))
+        .collect();
+    rows.sort_by_key(|(category, _, _)| category.to_string());
+    rows
+}
+
+fn exec_compare_periods(
+    store: &Store,
+    a: (NaiveDate, NaiveDate),
+    b: (NaiveDate, NaiveDate),
+    currency: &Currency,
+) {
+    //! Print the spending per category and in total in two periods side by side
+    //! with the change from the first to the second
+    let Some(purchases) = load_purchases(store) else {
+        return;
+    };
+    let rows: Vec<(ProductType, i64, i64)> = compare_periods(&purchases, a, b);
+    let change = |a: i64, b: i64| {
+        let sign: &str = if b > a { "+" } else { "" };
+        format!("{}{}", sign, format_amount(b - a, currency))
+    };
+    println!("{} to {} vs {} to {}", a.0, a.1, b.0, b.1);
+    for (category, a, b) in &rows {
+        println!(
+            "{}: {} vs {} ({})",
+            category,
+            format_amount(*a, currency),
+            format_amount(*b, currency),
+            change(*a, *b)
+        );
+    }
+    let total_a: i64 = rows.iter().map(|(_, a, _)| a).sum();
+    let total_b: i64 = rows.iter().map(|(_, _, b)| b).sum();
+    println!(
+        "Total: {} vs {} ({})",
+        format_amount(total_a, currency),
+        format_amount(total_b, currency),
+        change(total_a, total_b)
+    );
+}
+
+fn exec_recent(store: &Store, days: u32, currency: &Currency) {
+    //! Print the spending of the last days days up to and including today,
+    //! in total and per category
+    let today: NaiveDate = Local::now().date_naive();
+    let from: NaiveDate = today - chrono::Duration::days(i64::from(days.max(1)) - 1);
+    let Some(purchases) = load_purchases_between(store, Some(from), Some(today)) else {
+        return;
+    };
+    let mut totals: Vec<(String, i64)> = Vec::new();
+    for purchase in &purchases {
+        let category: String = purchase.product.product_type.to_string();
+        match totals.iter_mut().find(|(name, _)| *name == category) {
+            Some((_, total)) => *total += purchase.value(),
+            None => totals.push((category, purchase.value())),
+        }
+    }
+    totals.sort_by_key(|(_, total)| std::cmp::Reverse(*total));
+    println!("Last {} days ({} to {})", days.max(1), from, today);
+    for (category, total) in &totals {
+        println!("{}: {}", category, format_amount(*total, currency));
+    }
+    let total: i64 = totals.iter().map(|(_, total)| total).sum();
+    println!("Total: {}", format_amount(total, currency));
+}
+
+fn exec_categories(store: &Store) {
+    //! Print the name of every category, one per line
+    let Some(purchases) = load_purchases(store) else {
+        return;
+    };
+    for name in known_categories(&purchases) {
+        println!("{}", name);
+    }
+}
+
+// output format of stats, the machine formats for logging stats into a time series
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum OutputFormat {
+    Text,
+    Json,
+    Csv,
+}
+
+fn write_stats(
+    values: &[i64],
+    format: OutputFormat,
+    currency: &Currency,
+    out: &mut dyn Write,
+) -> std::io::Result<()> {
+    //! Write the count, total, mean, median, min, max and standard deviation of the values
+    //! The machine formats give amounts with exactly two decimals, rounded like the text;
+    //! CSV is a single row of the numbers in that order, without a header
+    let (Some(mean), Some(median), Some(std_dev), Some(&min), Some(&max)) = (
+        stats::mean(values),
+        stats::median(values),
+        stats::std_dev(values),
+        values.iter().min(),
+        values.iter().max(),
+    ) else {
+        return match format {
+            OutputFormat::Text => writeln!(out, "No purchases recorded yet"),
+            OutputFormat::Json => writeln!(out, "{{\"count\":0}}"),
+            OutputFormat::Csv => writeln!(out, "0,,,,,,"),
+        };
+    };
+    let amounts: [(&str, &str, i64); 6] = [
+        ("total", "total", values.iter().sum()),
+        ("mean", "mean", rounding::round_cents(mean)),
+        ("median", "median", rounding::round_cents(median)),
+        ("min", "min", min),
+        ("max", "max", max),
+        ("std dev", "std_dev", rounding::round_cents(std_dev)),
+    ];
+    match format {
+        OutputFormat::Text => {
+            writeln!(out, "count: {}", values.len())?;
+            for (label, _, cents) in amounts {
+                writeln!(out, "{}: {}", label, format_amount(cents, currency))?;
+            }
+        }
+        OutputFormat::Json => {
+            // written by hand, serde_json would drop trailing zeros of the amounts
+            let fields: Vec<String> = amounts
+                .iter()
+                .map(|(_, key, cents)| format!("\"{}\":{}", key, format_cents(*cents)))
+                .collect();
+            writeln!(out, "{{\"count\":{},{}}}", values.len(), fields.join(","))?;
+        }
+        OutputFormat::Csv => {
+            let fields: Vec<String> = amounts
+                .iter()
+                .map(|(_, _, cents)| format_cents(*cents))
+                .collect();
+            writeln!(out, "{},{}", values.len(), fields.join(","))?;
+        }
+    }
+    Ok(())
+}
+
+fn exec_stats(
+    store: &Store,
+    category: Option<&ProductType>,
+    format: OutputFormat,
+    currency: &Currency,
+    out: &mut dyn Write,
+) -> std::io::Result<()> {
+    //! Print summary statistics over the values of all purchases
+    //! or only those of the given category
+    let Some(purchases) = load_purchases(store) else {
+        return Ok(());
+    };
+    let purchases: Vec<&Purchase> = match category {
+        Some(category) => or_exit(filter_by_category(&purchases, category)),
+        None => purchases.iter().collect(),
+    };
+    let values: Vec<i64> = purchases.iter().map(|purchase| purchase.value()).collect();
+    write_stats(&values, format, currency, out)
+}
+
+enum TimeFrame {
+    Week,
+    Month,
+    Year,
+}
+
+fn compute_average_expenses(store: &Store, time_frame: TimeFrame, currency: &Currency) {
+    //! Compute average expenses per time_frame
+    //! by iterating over purchases and adding the value of each purchase
+    //! and dividing by the duration passed
+    let Some(purchases) = load_purchases(store) else {
+        return;
+    };
+    if purchases.is_empty() {
+        println!("No purchases recorded yet");
+        return;
+    }
+    let mut total_value: i64 = 0;
+    let start: NaiveDate = purchases.first().unwrap().date;
+    let end: NaiveDate = purchases.last().unwrap().date;
+    let diff: i64 = end.signed_duration_since(start).num_days();
+    let duration: i64 = match time_frame {
+        TimeFrame::Week => diff / 7 + 1,
+        TimeFrame::Month => diff / 30 + 1,
+        TimeFrame::Year => diff / 365 + 1,
+    };
+    for purchase in purchases {
+        total_value += purchase.value();
+    }
+    let label: &str = match time_frame {
+        TimeFrame::Week => "week",
+        TimeFrame::Month => "month",
+        TimeFrame::Year => "year",
+    };
+    println!(
+        "Average expenses per {}: {}",
+        label,
+        format_amount(
+            rounding::round_cents(total_value as f64 / duration as f64),
+            currency
+        )
+    );
+}
+
+fn extreme_purchase<'a>(purchases: &[&'a Purchase], largest: bool) -> Option<&'a Purchase> {
+    //! Find the purchase with the highest (or, if largest is false, the lowest) value
+    //! Ties go to the earliest purchase
+    purchases.iter().copied().min_by(|a, b| {
+        let ordering: Ordering = if largest {
+            b.value().cmp(&a.value())
+        } else {
+            a.value().cmp(&b.value())
+        };
+        ordering.then(a.date.cmp(&b.date))
+    })
+}
+
+fn exec_extreme(
+    store: &Store,
+    from: Option<NaiveDate>,
+    to: Option<NaiveDate>,
+    largest: bool,
+    currency: &Currency,
+) {
+    //! Print all details of the biggest (or smallest) purchase, optionally within a date range
+    let Some(purchases) = load_purchases_between(store, from, to) else {
+        return;
+    };
+    let purchases: Vec<&Purchase> = purchases.iter().collect();
+    let Some(purchase) = extreme_purchase(&purchases, largest) else {
+        println!("No purchases recorded in this period");
+        return;
+    };
+    print_purchase_details(purchase, currency);
+}
+
+fn note_suffix(purchase: &Purchase) -> String {
+    //! The note of a purchase to show after it in listings, empty without a note
+    match &purchase.note {
+        Some(note) => format!(" ({})", note),
+        None => String::new(),
+    }
+}
+
+fn print_purchase_details(purchase: &Purchase, currency: &Currency) {
+    //! Print every field of a purchase and its value, one per line
+    println!("name: {}", purchase.product.name);
+    println!("type: {}", purchase.product.product_type);
+    println!("brand: {}", purchase.product.brand);
+    // amounts in another currency are shown with its code
+    let amount = |cents: i64| match &purchase.currency {
+        Some(code) => format!("{} {}", format_cents(cents), code),
+        None => format_amount(cents, currency),
+    };
+    println!("price: {}", amount(purchase.product.price_cents));
+    println!("quantity: {}", purchase.quantity);
+    if purchase.refund {
+        println!("refund: yes");
+    }
+    println!("value: {}", amount(purchase.value()));
+    println!("date: {}", format_date(purchase.date));
+    println!("shop: {}", purchase.shop);
+    if !purchase.tags.is_empty() {
+        println!("tags: {}", purchase.tags.join(", "));
+    }
+    if let Some(note) = &purchase.note {
+        println!("note: {}", note);
+    }
+}
+
+fn daily_rate(purchases: impl IntoIterator<Item = Purchase>) -> Option<(f64, i64)> {
+    //! Average spending per day (in cents) and the number of days
+    //! from the earliest to the latest purchase, both inclusive
+    //! None if there are no purchases
+    //! The purchases are only folded over, so they can be streamed
+    let (start, end, total): (NaiveDate, NaiveDate, i64) =
+        purchases
+            .into_iter()
+            .fold(None, |range, purchase| match range {
+                None => Some((purchase.date, purchase.date, purchase.value())),
+                Some((start, end, total)) => Some((
+                    start.min(purchase.date),
+                    end.max(purchase.date),
+                    total + purchase.value(),
+                )),
+            })?;
+    let days: i64 = (end - start).num_days() + 1;
+    Some((total as f64 / days as f64, days))
+}
+
+fn exec_burn_rate(store: &Store, currency: &Currency) {
+    //! Print the average spending per day over the range of recorded purchases
+    //! streaming the purchases instead of loading them all
+    //! Prices in other currencies are converted like in load_purchases_between
+    let mut error: Option<ReadError> = None;
+    let mut unknown: Vec<String> = Vec::new();
+    let rate: Option<(f64, i64)> = daily_rate(
+        store
+            .iter()
+            .map_while(|purchase| {
+                purchase
+                    .map_err(|purchase_error| error = Some(purchase_error))
+                    .ok()
+            })
+            .map(|mut purchase| {
+                let rates: Option<&HashMap<String, i64>> = EXCHANGE_RATES.get();
+                match rates.and_then(|rates| convert_currency(&mut purchase, rates)) {
+                    Some(code) if !unknown.contains(&code) => unknown.push(code),
+                    _ => {}
+                }
+                purchase
+            }),
+    );
+    if EXCHANGE_RATES.get().is_some() {
+        for code in unknown {
+            warn(format!(
+                "no exchange rate for {}, its prices are not converted",
+                code
+            ));
+        }
+    }
+    match error {
+        // a data file that does not exist yet holds no purchases
+        Some(ReadError::IoError(error)) if error.kind() == std::io::ErrorKind::NotFound => {}
+        Some(error) => {
+            println!("Could not load purchases: {}", error);
+            return;
+        }
+        None => {}
+    }
+    let Some((rate, days)) = rate else {
+        println!("No purchases recorded yet, cannot compute a rate");
+        return;
+    };
+    println!(
+        "avg {}/day over {} days",
+        format_amount(rounding::round_cents(rate), currency),
+        days
+    );
+}
+
+// upper edges of the default histogram ranges: 0-10, 10-50, 50-100 and 100+
+const DEFAULT_HISTOGRAM_EDGES: &[i64] = &[1000, 5000, 10000];
+
+fn parse_edges(edges: &str) -> Result<Vec<i64>, String> {
+    //! Parse comma-separated histogram edges like "10,50,100" into cents
+    //! The edges must be increasing
+    let edges: Vec<i64> = edges
+        .split(',')
+        .map(|edge| {
+            parse_cents(edge)
+                .ok_or_else(|| format!("--buckets edge '{}' is not a number", edge.trim()))
+        })
+        .collect::<Result<Vec<i64>, String>>()?;
+    if edges.windows(2).any(|pair| pair[0] >= pair[1]) {
+        return Err(String::from("--buckets edges must be increasing"));
+    }
+    Ok(edges)
+}
+
+fn value_histogram(purchases: &[Purchase], edges: &[i64]) -> Vec<(usize, i64)> {
+    //! Count and sum the purchases (by value) falling in each range between the edges,
+    //! starting with the range below the first edge and ending with the one from the last edge up
+    //! A value exactly on an edge belongs to the higher range
+    let mut ranges: Vec<(usize, i64)> = vec![(0, 0); edges.len() + 1];
+    for purchase in purchases {
+        let value: i64 = purchase.value();
+        let range: &mut (usize, i64) = &mut ranges[edges.partition_point(|&edge| edge <= value)];
+        range.0 += 1;
+        range.1 += value;
+    }
+    ranges
+}
+
+fn exec_histogram(store: &Store, edges: &[i64], chart: Option<usize>, currency: &Currency) {
+    //! Print the number and total of purchases in each value range,
+    //! drawing the counts as bars at most chart characters wide if given
+    let Some(purchases) = load_purchases(store) else {
+        return;
+    };
+    if purchases.is_empty() {
+        println!("No purchases recorded yet");
+        return;
+    }
+    let ranges: Vec<(usize, i64)> = value_histogram(&purchases, edges);
+    let labels: Vec<String> = (0..ranges.len())
+        .map(|index| {
+            match (
+                index.checked_sub(1).map(|lower| edges[lower]),
+                edges.get(index),
+            ) {
+                (lower, Some(upper)) => format!(
+                    "{}–{}",
+                    format_amount(lower.unwrap_or(0), currency),
+                    format_amount(*upper, currency)
+                ),
+                (lower, None) => format!("{}+", format_amount(lower.unwrap_or(0), currency)),
+            }
+        })
+        .collect();
+    let label_width: usize = labels
+        .iter()
+        .map(|label| label.chars().count())
+        .max()
+        .unwrap_or(0);
+    let rows: Vec<String> = labels
+        .iter()
+        .zip(&ranges)
+        .map(|(label, (count, total))| {
+            format!(
+                "{:<label_width$} {} purchases, {}",
+                label,
+                count,
+                format_amount(*total, currency)
+            )
+        })
+        .collect();
+    let Some(width) = chart else {
+        rows.iter().for_each(|row| println!("{}", row));
+        return;
+    };
+    let row_width: usize = rows
+        .iter()
+        .map(|row| row.chars().count())
+        .max()
+        .unwrap_or(0);
+    let max: usize = ranges.iter().map(|(count, _)| *count).max().unwrap_or(0);
+    for (row, (count, _)) in rows.iter().zip(&ranges) {
+        println!(
+            "{:<row_width$} |{}",
+            row,
+            bar(*count as i64, max as i64, width)
+        );
+    }
+}
+
+const USAGE: &str = "usage: fima [options] <command>
+
+commands:
+    add [--dry-run] [--refund]
+                          add purchases interactively; with --dry-run validate a
+                          single purchase and print it as it would be stored,
+                          exiting with 1 on invalid input instead of asking again;
+                          with --refund record money back for returned products,
+                          stored with a negative quantity and subtracted in reports
+    receipt               add the items of one receipt, sharing date and shop
+    import                append purchases piped in on stdin (text format)
+    delete                delete a purchase by index
+    edit                  edit a purchase by index
+    recur [MONTHS]        add a purchase recurring monthly for MONTHS months
+    dedupe                find duplicate purchases and offer to remove them
+    check                 list every problem of the data file (malformed lines,
+                          invalid values, future dates and duplicates) with its
+                          line, exiting with 1 if there are any and printing OK
+                          otherwise; the file is not changed
+    outliers [--factor X] list purchases whose price is more than X times above or
+                          below the median price of the product (default: 3),
+                          for products bought at least 3 times
+    list [--min-price X] [--max-price Y] [--sort KEY] [--reverse] [--limit N] [--offset M]
+         [--category C]   list purchases with their indices sorted by date
+                          (or by KEY: date, price, name or value), optionally
+                          only those with a unit price within the range or
+                          of category C, and only N of them starting after
+                          the first M
+    report [--from DATE] [--to DATE] [--top N] [--units] [--chart] [--chart-width N]
+                          compare spending per category, optionally within
+                          an inclusive date range (dates as yyyy-mm-dd),
+                          showing only the N highest categories and the
+                          number of units bought per category, and drawing
+                          a bar chart at most N characters wide (default 40)
+    report --expand-other PERCENT
+                          list the products in the other category when it makes
+                          up at least PERCENT of the total (default: 10)
+    report --json         write the total and the value per category as JSON
+    report --no-cache     recompute the categories even if the data file is unchanged
+                          (without a date range or --adjust-to they are kept in a
+                          cache file next to it, e.g. purchase.txt.cache)
+    report --adjust-to YEAR [--inflation PATH]
+                          adjust values to the price level of YEAR using the
+                          \"year, index\" lines in PATH (default inflation.txt)
+    browse                browse the purchases in a scrollable table, filtering by
+                          category (c), sorting (s, r to reverse) and showing the
+                          total of the rows shown (needs the tui feature)
+    rename-category OLD NEW
+                          change the category of every purchase of category OLD
+                          to NEW (a built-in, an alias or a custom category)
+    archive --before DATE --to PATH
+                          move the purchases dated before DATE to the end of the
+                          archive file PATH, e.g. to combine them again with
+                          --file purchase.txt,PATH
+    migrate SOURCE        copy the purchases of the text data file SOURCE into the
+                          data file or database, e.g. with --backend sqlite
+    export-csv PATH       export all purchases as CSV for spreadsheets
+    stats [--category C]  count, total, mean, median, min, max and standard
+                          deviation of purchase values (of category C)
+    stats --output-format text|json|csv
+                          write the statistics as text (the default), as a JSON
+                          object or as a CSV row of count,total,mean,median,min,
+                          max,std_dev, with amounts to two decimals
+    monthly [--budgets PATH]
+                          total spending per calendar month; with budgets
+                          (lines of \"category, monthly limit\" in PATH,
+                          default budgets.txt) also per category, marking
+                          categories over budget
+                          and the categories that grew and shrank the most
+                          between the two most recent months
+    target [AMOUNT] [--target-file PATH]
+                          set the monthly spending target (stored in PATH,
+                          default target.txt), or show the spending of the
+                          current month projected to its end against it
+    income [AMOUNT [SOURCE]] [--income PATH]
+                          record income of AMOUNT dated today as \"date, amount,
+                          source\" lines in PATH (default income.txt), or list it
+    balance [monthly] [--income PATH]
+                          all income minus all spending, with monthly also the
+                          income, spending, net and balance of each month
+    goal [NAME CAP] [--from DATE] [--to DATE] [--goals PATH]
+                          set a goal of spending at most CAP from DATE to DATE
+                          (default: the current month), stored as \"name, from,
+                          to, cap\" lines in PATH (default goals.txt), or show
+                          each goal's spending, days left and whether it is on
+                          track, or met or missed once its period has ended
+    velocity [--threshold PERCENT]
+                          daily spending of this month so far against that of the
+                          complete months before, warning if it is more than
+                          PERCENT higher (default: 20)
+    projected             spending per category so far this year and scaled
+                          linearly to the whole year
+    what-if --exclude CATEGORY
+                          how much less would have been spent without CATEGORY;
+                          give it several times or as a comma-separated list
+    compare-periods --from DATE --to DATE --from DATE --to DATE
+                          spending per category in two periods side by side
+    recent [--days N]     spending of the last N days including today (default: 30)
+    categories            built-in categories and the custom ones in use
+    typical               average spending per category in a month, over the
+                          complete months recorded, as suggested budgets
+    weekly                total spending per ISO week
+    quarterly [--by-category]
+                          total spending per calendar quarter, optionally
+                          also per category
+    cumulative [--chart] [--chart-width N]
+                          running total of spending at each date with purchases
+    weekdays [--chart] [--chart-width N]
+                          total spending per day of the week and its average
+                          per such day from the first to the last purchase
+    days [--top N]        the N dates with the highest and the lowest spending
+                          (default: 5), where dates between the first and the
+                          last purchase without any count as zero
+    trend                 monthly totals with the change from the previous month
+                          (months without purchases count as zero)
+    yearly                spending per category and year
+    by-tag [TAG]          spending per tag, or the total of TAG
+    frequent [--top N] [--by quantity|value]
+                          the N products bought most often (default: 10),
+                          or with --by value those spent most on
+    drilldown CATEGORY    the products of CATEGORY with their value and quantity,
+                          highest value first, and the total of the category
+    search QUERY [--min-price X] [--max-price Y]
+                          find purchases whose product name contains QUERY
+    average [week|month|year]
+                          average expenses per time frame (default: week)
+    max [--from DATE] [--to DATE]
+                          details of the purchase with the highest value
+    min [--from DATE] [--to DATE]
+                          details of the purchase with the lowest value
+    burn-rate             average spending per day from the first to the last purchase
+    histogram [--buckets EDGES] [--chart] [--chart-width N]
+                          number and total of purchases per value range, split
+                          at the comma-separated EDGES (default: 10,50,100);
+                          a value on an edge counts to the higher range
+
+options:
+    -f, --file PATH       data file to use (default: $FIMA_FILE, then purchase.txt);
+                          give it several times or as a comma-separated list
+                          to combine the purchases of several files in reports;
+                          text files ending in .gz are kept gzip-compressed
+                          (needs the gzip feature)
+    --format json|text    format of the data file (default: text)
+    --backend text|sqlite store purchases in the data file or in an SQLite database
+                          (default: text; sqlite needs the sqlite feature and
+                          defaults to purchase.db)
+    --currency SYMBOL     currency symbol shown in front of amounts (default: $);
+                          report --json gives its ISO code, e.g. USD for $
+    --decimals N          number of decimal places shown (default: 2)
+    --decimal-separator C character separating the decimals (default: .)
+    --date-format FORMAT  format of dates entered and listed, like %d/%m/%Y
+                          (default: yyyy-mm-dd, also accepting dd-mm-yyyy,
+                          dd.mm.yyyy and mm/dd/yyyy when entering); the data file
+                          always keeps yyyy-mm-dd
+    --aliases PATH        \"alias, category\" lines mapping other names to categories,
+                          in addition to built-in ones like groceries for food
+                          (default: aliases.txt)
+    --rates PATH          \"code, rate\" lines giving the value of one unit of another
+                          currency in the one shown (default: rates.txt); prices
+                          entered like 12.50 EUR are converted with them in reports
+    --out PATH            write the output of report, monthly and stats to PATH
+                          instead of the terminal
+    --confirm-above AMOUNT
+                          when adding or editing several items worth more than
+                          AMOUNT in total, ask whether the price entered is the
+                          price per item or the total (default: 500.00)
+    --no-confirm          never ask that, e.g. in scripts
+    -v, --verbose         log file operations and skipped lines on stderr, with -vv
+                          also every purchase read
+    --no-color            do not color totals and budgets (with the color feature,
+                          colors are also off when NO_COLOR is set or the output
+                          is not a terminal)
+
+Defaults for any option can be kept in fima.toml in the current directory or in
+~/.config/fima/config.toml, keyed by option name, e.g. currency = \"€\" or top = 5;
+options on the command line take precedence (and FIMA_FILE over a configured file).
+
+Amounts are shown like 1,299.00 with FIMA_LOCALE=en-US or 1.299,00 € with de-DE
+(also en-GB, es-ES, it-IT and fr-FR); the data file is not affected.";
+
+const DEFAULT_FILE: &str = "purchase.txt";
+#[cfg(feature = "sqlite")]
+const DEFAULT_DATABASE_FILE: &str = "purchase.db";
+
+const DEFAULT_BUDGETS_FILE: &str = "budgets.txt";
+const DEFAULT_CONFIG_FILE: &str = "fima.toml";
+// 500.00, see confirm_unit_price
+const DEFAULT_CONFIRM_ABOVE: i64 = 50000;
+const DEFAULT_INFLATION_FILE: &str = "inflation.txt";
+const DEFAULT_ALIASES_FILE: &str = "aliases.txt";
+const DEFAULT_RATES_FILE: &str = "rates.txt";
+const DEFAULT_TARGET_FILE: &str = "target.txt";
+const DEFAULT_INCOME_FILE: &str = "income.txt";
+const DEFAULT_GOALS_FILE: &str = "goals.txt";
+
+// options which take a value, e.g. `--file purchase.txt`
+const VALUE_OPTIONS: &[&str] = &[
+    "file",
+    "format",
+    "from",
+    "to",
+    "currency",
+    "decimals",
+    "decimal-separator",
+    "date-format",
+    "top",
+    "min-price",
+    "max-price",
+    "budgets",
+    "sort",
+    "chart-width",
+    "buckets",
+    "expand-other",
+    "confirm-above",
+    "by",
+    "output-format",
+    "factor",
+    "exclude",
+    "before",
+    "threshold",
+    "out",
+    "backend",
+    "adjust-to",
+    "inflation",
+    "aliases",
+    "rates",
+    "category",
+    "target-file",
+    "income",
+    "goals",
+    "days",
+    "limit",
+    "offset",
+];
+
+// options which take no value, e.g. `--reverse`
+const FLAGS: &[&str] = &[
+    "reverse",
+    "units",
+    "dry-run",
+    "chart",
+    "json",
+    "no-color",
+    "refund",
+    "no-confirm",
+    "by-category",
+    "no-cache",
+    "verbose",
+];
+
+// short aliases for long options
+const SHORT_OPTIONS: &[(&str, &str)] = &[("-f", "file")];
+
+struct Args {
+    command: Option<String>,
+    positional: Vec<String>,
+    options: Vec<(String, String)>,
+    flags: Vec<String>,
+}
+
+impl Args {
+    fn flag(&self, name: &str) -> bool {
+        //! Check whether a flag was passed
+        self.flags.iter().any(|flag| flag == name)
+    }
+
+    fn count(&self, name: &str) -> usize {
+        //! Number of times a flag was passed
+        self.flags.iter().filter(|flag| *flag == name).count()
+    }
+
+    fn value(&self, name: &str) -> Option<&str> {
+        //! Get the value of an option (the last one if given several times)
+        self.options
+            .iter()
+            .rev()
+            .find(|(option, _)| option == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    fn number<T: std::str::FromStr>(&self, name: &str) -> Result<Option<T>, String> {
+        //! Get the value of an option as a number
+        match self.value(name) {
+            None => Ok(None),
+            Some(value) => value
+                .parse()
+                .map(Some)
+                .map_err(|_| format!("--{} must be a number, got '{}'", name, value)),
+        }
+    }
+
+    fn amount(&self, name: &str) -> Result<Option<i64>, String> {
+        //! Get the value of an option as an amount in cents
+        match self.value(name) {
+            None => Ok(None),
+            Some(value) => parse_cents(value)
+                .map(Some)
+                .ok_or_else(|| format!("--{} must be a decimal number like 12.34", name)),
+        }
+    }
+
+    fn date(&self, name: &str) -> Result<Option<NaiveDate>, String> {
+        //! Get the value of an option as a yyyy-mm-dd date
+        match self.value(name) {
+            None => Ok(None),
+            Some(value) => NaiveDate::parse_from_str(value, "%Y-%m-%d")
+                .map(Some)
+                .map_err(|_| format!("--{} must be a date in the format yyyy-mm-dd", name)),
+        }
+    }
+
+    fn with_defaults(mut self, defaults: Args) -> Args {
+        //! Add the options and flags of defaults (like those of a config file) that were not given
+        for (name, value) in defaults.options {
+            if self.value(&name).is_none() {
+                self.options.push((name, value));
+            }
+        }
+        for flag in defaults.flags {
+            if !self.flag(&flag) {
+                self.flags.push(flag);
+            }
+        }
+        self
+    }
+
+    fn values(&self, name: &str) -> Vec<&str> {
+        //! Get every value of an option given several times, in order
+        self.options
+            .iter()
+            .filter(|(option, _)| option == name)
+            .map(|(_, value)| value.as_str())
+            .collect()
+    }
+
+    fn dates(&self, name: &str) -> Result<Vec<NaiveDate>, String> {
+        //! Get every value of an option given several times as yyyy-mm-dd dates, in order
+        self.values(name)
+            .into_iter()
+            .map(|value| {
+                NaiveDate::parse_from_str(value, "%Y-%m-%d")
+                    .map_err(|_| format!("--{} must be a date in the format yyyy-mm-dd", name))
+            })
+            .collect()
+    }
+}
+
+fn parse_args(args: impl Iterator<Item = String>) -> Result<Args, String> {
+    //! Parse command line arguments (without the program name)
+    //! into a subcommand, its positional arguments, options and flags
+    //! Options may appear anywhere, either as `--name value` or `--name=value`
+    let mut parsed = Args {
+        command: None,
+        positional: Vec::new(),
+        options: Vec::new(),
+        flags: Vec::new(),
+    };
+    let mut args = args;
+    while let Some(arg) = args.next() {
+        // -v raises the verbosity by one, -vv by two and so on
+        if let Some(vs) = arg
+            .strip_prefix('-')
+            .filter(|vs| !vs.is_empty() && vs.chars().all(|c| c == 'v'))
+        {
+            parsed
+                .flags
+                .extend(vs.chars().map(|_| String::from("verbose")));
+            continue;
+        }
+        let name: Option<String> = if let Some(long) = arg.strip_prefix("--") {
+            Some(long.to_string())
+        } else {
+            SHORT_OPTIONS
+                .iter()
+                .find(|(short, _)| *short == arg)
+                .map(|(_, long)| long.to_string())
+        };
+        match name {
+            Some(name) => {
+                if let Some((name, value)) = name
+                    .split_once('=')
+                    .filter(|(name, _)| VALUE_OPTIONS.contains(name))
+                {
+                    parsed.options.push((name.to_string(), value.to_string()));
+                } else if VALUE_OPTIONS.contains(&name.as_str()) {
+                    let value: String = args
+                        .next()
+                        .ok_or_else(|| format!("option --{} requires a value", name))?;
+                    parsed.options.push((name, value));
+                } else if FLAGS.contains(&name.as_str()) {
+                    parsed.flags.push(name);
+                } else {
+                    let name: &str = name.split('=').next().unwrap_or_default();
+                    return Err(format!("unknown option --{}", name));
+                }
+            }
+            None if parsed.command.is_none() => parsed.command = Some(arg),
+            None => parsed.positional.push(arg),
+        }
+    }
+    Ok(parsed)
+}
+
+fn parse_config(contents: &str, file_name: &str) -> Result<Args, String> {
+    //! Parse a TOML config file of defaults, keyed by long option name
+    //! like currency = "€", file = "home.txt" or top = 5; flags are booleans like units = true
+    let table: toml::Table = toml::from_str(contents)
+        .map_err(|error| format!("invalid config {}: {}", file_name, error))?;
+    let mut config = Args {
+        command: None,
+        positional: Vec::new(),
+        options: Vec::new(),
+        flags: Vec::new(),
+    };
+    for (name, value) in table {
+        let value: String = match value {
+            toml::Value::Boolean(set) if FLAGS.contains(&name.as_str()) => {
+                if set {
+                    config.flags.push(name);
+                }
+                continue;
+            }
+            toml::Value::String(value) => value,
+            toml::Value::Integer(value) => value.to_string(),
+            toml::Value::Float(value) => value.to_string(),
+            _ if VALUE_OPTIONS.contains(&name.as_str()) => {
+                return Err(format!(
+                    "invalid config {}: {} must be a string or a number",
+                    file_name, name
+                ))
+            }
+            _ if FLAGS.contains(&name.as_str()) => {
+                return Err(format!(
+                    "invalid config {}: {} must be true or false",
+                    file_name, name
+                ))
+            }
+            _ => {
+                return Err(format!(
+                    "invalid config {}: unknown option {}",
+                    file_name, name
+                ))
+            }
+        };
+        if !VALUE_OPTIONS.contains(&name.as_str()) {
+            return Err(format!(
+                "invalid config {}: unknown option {}",
+                file_name, name
+            ));
+        }
+        config.options.push((name, value));
+    }
+    Ok(config)
+}
+
+fn config_file() -> Option<String> {
+    //! The config file to use: fima.toml in the current directory if it exists,
+    //! otherwise fima/config.toml in $XDG_CONFIG_HOME (default ~/.config) if that exists
+    let home_config: Option<String> = std::env::var("XDG_CONFIG_HOME")
+        .ok()
+        .filter(|dir| !dir.is_empty())
+        .or_else(|| {
+            std::env::var("HOME")
+                .ok()
+                .map(|home| format!("{}/.config", home))
+        })
+        .map(|dir| format!("{}/fima/config.toml", dir));
+    std::iter::once(String::from(DEFAULT_CONFIG_FILE))
+        .chain(home_config)
+        .find(|file_name| std::path::Path::new(file_name).is_file())
+}
+
+fn read_config() -> Result<Option<Args>, String> {
+    //! Read the defaults of the config file, if there is one, see config_file
+    let Some(file_name) = config_file() else {
+        return Ok(None);
+    };
+    let contents: String = std::fs::read_to_string(&file_name)
+        .map_err(|error| format!("could not read {}: {}", file_name, error))?;
+    parse_config(&contents, &file_name).map(Some)
+}
+
+fn resolve_files(args: &Args, default: &str) -> Vec<String> {
+    //! Determine the data files
+    //! from the --file options (each may be a comma-separated list),
+    //! then the FIMA_FILE environment variable, then the given default
+    let files: Vec<String> = args
+        .values("file")
+        .into_iter()
+        .flat_map(|files| files.split(','))
+        .map(str::trim)
+        .filter(|file_name| !file_name.is_empty())
+        .map(String::from)
+        .collect();
+    if !files.is_empty() {
+        return files;
+    }
+    vec![std::env::var("FIMA_FILE").unwrap_or_else(|_| default.to_string())]
+}
+
+fn format_from_args(args: &Args) -> Result<Format, String> {
+    //! Determine the store from the --backend and --format options
+    match args.value("backend") {
+        None | Some("text") => match args.value("format") {
+            None | Some("text") => Ok(Format::Text),
+            Some("json") => Ok(Format::Json),
+            Some(other) => Err(format!("unknown format '{}', expected json or text", other)),
+        },
+        #[cfg(feature = "sqlite")]
+        Some("sqlite") => Ok(Format::Sqlite),
+        #[cfg(not(feature = "sqlite"))]
+        Some("sqlite") => Err(String::from(
+            "the sqlite backend is not available, build fima with --features sqlite",
+        )),
+        Some(other) => Err(format!(
+            "unknown backend '{}', expected text or sqlite",
+            other
+        )),
+    }
+}
+
+fn exec_migrate(store: &Store, source: &str) {
+    //! Copy all purchases of the text data file source into the store
+    //! after the purchases already in it
+    let purchases: Vec<Purchase> = match read_from_file(source) {
+        Ok(purchases) => purchases,
+        Err(error) => {
+            eprintln!("Could not read {}: {}", source, error);
+            std::process::exit(1);
+        }
+    };
+    let migrated = store.modify(|all| {
+        all.extend(purchases.iter().cloned());
+        Ok::<(), ReadError>(())
+    });
+    if let Err(error) = migrated {
+        eprintln!("Could not migrate purchases: {}", error);
+        std::process::exit(1);
+    }
+    println!("Migrated {} purchases from {}", purchases.len(), source);
+}
+
+fn output_from_args(args: &Args) -> Result<Box<dyn Write>, String> {
+    //! Open the destination of a command's output
+    //! the file given with --out (created or truncated), or stdout
+    match args.value("out") {
+        Some(path) => File::create(path)
+            .map(|file| Box::new(file) as Box<dyn Write>)
+            .map_err(|error| format!("could not create {}: {}", path, error)),
+        None => Ok(Box::new(std::io::stdout())),
+    }
+}
+
+fn or_exit_append(result: std::io::Result<u64>) -> u64 {
+    //! Exit with a nonzero status if a purchase could not be added to the data file,
+    //! otherwise give the length of the store before (see Store::append)
+    or_exit(result.map_err(|error| format!("could not add purchase: {}", error)))
+}
+
+fn or_exit_write(result: std::io::Result<()>) {
+    //! Exit with a nonzero status if writing a command's output failed
+    or_exit(result.map_err(|error| format!("could not write output: {}", error)))
+}
+
+fn currency_from_args(args: &Args) -> Result<Currency, String> {
+    //! Build the display currency from the command line options
+    //! falling back to the conventions of the FIMA_LOCALE environment variable,
+    //! then to the default for any option not given
+    let mut currency: Currency = match std::env::var("FIMA_LOCALE") {
+        Ok(locale) if !locale.is_empty() => Currency::from_locale(&locale).ok_or_else(|| {
+            let names: Vec<&str> = LOCALES.iter().map(|(name, _, _, _, _)| *name).collect();
+            format!(
+                "unknown FIMA_LOCALE '{}', expected one of: {}",
+                locale,
+                names.join(", ")
+            )
+        })?,
+        _ => Currency::default(),
+    };
+    if let Some(symbol) = args.value("currency") {
+        currency.symbol = symbol.to_string();
+    }
+    if let Some(decimals) = args.value("decimals") {
+        currency.decimals = decimals
+            .parse()
+            .map_err(|_| String::from("--decimals must be a non-negative integer"))?;
+    }
+    if let Some(separator) = args.value("decimal-separator") {
+        let mut chars = separator.chars();
+        currency.decimal_separator = match (chars.next(), chars.next()) {
+            (Some(separator), None) => separator,
+            _ => {
+                return Err(String::from(
+                    "--decimal-separator must be a single character",
+                ))
+            }
+        };
+    }
+    Ok(currency)
+}
+
+#[derive(Debug, Clone, Copy)]
+enum SortKey {
+    Date,
+    Price,
+    Name,
+    Value,
+}
+
+impl SortKey {
+    fn from_string(sort_key: &str) -> Option<SortKey> {
+        //! Convert a string to a SortKey
+        match sort_key {
+            "date" => Some(SortKey::Date),
+            "price" => Some(SortKey::Price),
+            "name" => Some(SortKey::Name),
+            "value" => Some(SortKey::Value),
+            _ => None,
+        }
+    }
+
+    fn compare(&self, a: &Purchase, b: &Purchase) -> Ordering {
+        //! Compare two purchases by this key
+        match self {
+            SortKey::Date => a.date.cmp(&b.date),
+            SortKey::Price => a.product.price_cents.cmp(&b.product.price_cents),
+            SortKey::Name => a.product.key().cmp(&b.product.key()),
+            SortKey::Value => a.value().cmp(&b.value()),
+        }
+    }
+}
+
+struct ListOptions {
+    min_price: Option<i64>,
+    max_price: Option<i64>,
+    sort: Option<SortKey>,
+    reverse: bool,
+    limit: Option<usize>,
+    offset: usize,
+    category: Option<ProductType>,
+}
+
+fn exec_list(store: &Store, options: &ListOptions, currency: &Currency) {
+    //! List purchases together with their indices, each with its date, name,
+    //! quantity, unit price and value
+    //! Purchases are sorted by the given key (by date if none is given, or by price
+    //! descending if a price bound is given); ties keep their order in the file
+    //! If a price bound is given, only purchases within the price range are listed
+    //! With a limit or offset only that page of the sorted purchases is listed
+    let Some(purchases) = load_purchases(store) else {
+        return;
+    };
+    if purchases.is_empty() {
+        println!("No purchases recorded yet");
+        return;
+    }
+    // reject unknown categories before filtering by them below
+    if let Some(category) = &options.category {
+        or_exit(filter_by_category(&purchases, category));
+    }
+    let price_filtered: bool = options.min_price.is_some() || options.max_price.is_some();
+    let mut matches: Vec<(usize, &Purchase)> = purchases
+        .iter()
+        .enumerate()
+        .filter(|(_, purchase)| in_price_range(purchase, options.min_price, options.max_price))
+        .filter(|(_, purchase)| {
+            options
+                .category
+                .as_ref()
+                .is_none_or(|category| purchase.product.product_type == *category)
+        })
+        .collect();
+    let (sort, reverse): (SortKey, bool) = match options.sort {
+        Some(sort) => (sort, options.reverse),
+        None if price_filtered => (SortKey::Price, !options.reverse),
+        None => (SortKey::Date, options.reverse),
+    };
+    // sort is stable, so ties keep file order even when reversed
+    matches.sort_by(|(_, a), (_, b)| {
+        let ordering: Ordering = sort.compare(a, b);
+        if reverse {
+            ordering.reverse()
+        } else {
+            ordering
+        }
+    });
+    let total: usize = matches.len();
+    let paged: bool = options.limit.is_some() || options.offset > 0;
+    // an offset past the end gives an empty page
+    let start: usize = options.offset.min(total);
+    let end: usize = match options.limit {
+        Some(limit) => start.saturating_add(limit).min(total),
+        None => total,
+    };
+    for &(index, purchase) in &matches[start..end] {
+        println!(
+            "[{}] {} {} x{} @ {} = {}{}",
+            index,
+            format_date(purchase.date),
+            purchase.product.name,
+            purchase.signed_quantity(),
+            format_amount(purchase.product.price_cents, currency),
+            format_amount(purchase.value(), currency),
+            note_suffix(purchase)
+        );
+    }
+    if paged {
+        println!("showing {}..{} of {}", start, end, total);
+    }
+}
+
+fn write_csv(purchases: &[Purchase], writer: &mut impl Write) -> std::io::Result<()> {
+    //! Write purchases as CSV with a header row
+    //! including a computed value column (price * quantity)
+    writeln!(writer, "name,price,type,quantity,date,value")?;
+    for purchase in purchases {
+        writeln!(
+            writer,
+            "{},{},{},{},{},{}",
+            quote_field(&purchase.product.name),
+            format_cents(purchase.product.price_cents),
+            quote_field(&purchase.product.product_type.to_string()),
+            purchase.signed_quantity(),
+            purchase.date,
+            format_cents(purchase.value())
+        )?;
+    }
+    Ok(())
+}
+
+fn exec_export_csv(store: &Store, output: &str) {
+    //! Export every purchase to a CSV file for spreadsheet import
+    let Some(purchases) = load_purchases(store) else {
+        return;
+    };
+    let result = File::create(output).and_then(|mut file| write_csv(&purchases, &mut file));
+    match result {
+        Ok(()) => println!("Exported {} purchases to {}", purchases.len(), output),
+        Err(error) => println!("Could not write {}: {}", output, error),
+    }
+}
+
+fn or_exit<T>(result: Result<T, String>) -> T {
+    //! Unwrap the result of parsing an option
+    //! or print the error and exit with a nonzero status
+    match result {
+        Ok(value) => value,
+        Err(error) => {
+            eprintln!("{}", error);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn main() {
+    let mut args: Args = match parse_args(std::env::args().skip(1)) {
+        Ok(args) => args,
+        Err(error) => {
+            eprintln!("{}\n\n{}", error, USAGE);
+            std::process::exit(1);
+        }
+    };
+    if let Some(mut config) = or_exit(read_config()) {
+        // the FIMA_FILE environment variable is more specific than a config file
+        if std::env::var_os("FIMA_FILE").is_some() {
+            config.options.retain(|(name, _)| name != "file");
+        }
+        args = args.with_defaults(config);
+    }
+    logging::init(args.count("verbose"));
+    let aliases_file: &str = args.value("aliases").unwrap_or(DEFAULT_ALIASES_FILE);
+    USER_ALIASES
+        .set(or_exit(read_aliases(aliases_file)))
+        .unwrap();
+    let format: Format = or_exit(format_from_args(&args));
+    let default_file: &str = match format {
+        #[cfg(feature = "sqlite")]
+        Format::Sqlite => DEFAULT_DATABASE_FILE,
+        _ => DEFAULT_FILE,
+    };
+    let mut files: Vec<String> = resolve_files(&args, default_file);
+    let store = Store {
+        file_name: files.remove(0),
+        format,
+        merged: files,
+    };
+    #[cfg(not(feature = "gzip"))]
+    if std::iter::once(&store.file_name)
+        .chain(&store.merged)
+        .any(|file_name| gzip::is_compressed(file_name))
+    {
+        eprintln!("{}", gzip::unsupported());
+        std::process::exit(1);
+    }
+    let changes_purchases: bool = matches!(
+        args.command.as_deref(),
+        Some(
+            "add"
+                | "receipt"
+                | "import"
+                | "delete"
+                | "edit"
+                | "recur"
+                | "dedupe"
+                | "migrate"
+                | "rename-category"
+                | "archive"
+        )
+    );
+    if !store.merged.is_empty() && changes_purchases {
+        eprintln!("several data files can only be read, pass a single --file to change purchases");
+        std::process::exit(1);
+    }
+    if !changes_purchases {
+        let rates_file: &str = args.value("rates").unwrap_or(DEFAULT_RATES_FILE);
+        EXCHANGE_RATES.set(or_exit(read_rates(rates_file))).unwrap();
+    }
+    if let Some(format) = args.value("date-format") {
+        DATE_FORMAT.set(or_exit(check_date_format(format))).unwrap();
+    }
+    let currency: Currency = or_exit(currency_from_args(&args));
+    // NO_COLOR counts only when set to something, see https://no-color.org
+    let no_color: bool = std::env::var("NO_COLOR").is_ok_and(|value| !value.is_empty());
+    color::set_enabled(
+        !args.flag("no-color")
+            && !no_color
+            && args.value("out").is_none()
+            && std::io::stdout().is_terminal(),
+    );
+    // values above this are confirmed to be entered with the price per item
+    let confirm_above: Option<i64> = (!args.flag("no-confirm"))
+        .then(|| or_exit(args.amount("confirm-above")).unwrap_or(DEFAULT_CONFIRM_ABOVE));
+    match args.command.as_deref() {
+        Some("add") => cli_update(
+            &store,
+            args.flag("dry-run"),
+            args.flag("refund"),
+            confirm_above,
+            &currency,
+        ),
+        Some("receipt") => cli_receipt(&store, confirm_above, &currency),
+        Some("import") => cli_import(&store, &currency),
+        Some("delete") => cli_delete(&store),
+        Some("edit") => cli_edit(&store, confirm_above),
+        Some("recur") => {
+            let months: Option<u32> =
+                args.positional.first().map(|months| {
+                    or_exit(months.parse().map_err(|_| {
+                        String::from("number of months must be a non-negative integer")
+                    }))
+                });
+            cli_recur(&store, months, confirm_above);
+        }
+        Some("dedupe") => cli_dedupe(&store),
+        Some("check") => exec_check(&store),
+        Some("archive") => {
+            let (Some(before), Some(archive)) = (or_exit(args.date("before")), args.value("to"))
+            else {
+                eprintln!("archive needs --before DATE and --to PATH\n\n{}", USAGE);
+                std::process::exit(1);
+            };
+            exec_archive(&store, archive, before);
+        }
+        Some("outliers") => {
+            let factor: f64 = or_exit(args.number("factor")).unwrap_or(DEFAULT_OUTLIER_FACTOR);
+            if factor <= 1.0 {
+                eprintln!("--factor must be greater than 1");
+                std::process::exit(1);
+            }
+            exec_outliers(&store, factor, &currency);
+        }
+        Some("list") => {
+            let sort: Option<SortKey> = args.value("sort").map(|sort| {
+                or_exit(SortKey::from_string(sort).ok_or_else(|| {
+                    format!(
+                        "unknown sort key '{}', expected date, price, name or value",
+                        sort
+                    )
+                }))
+            });
+            let options = ListOptions {
+                min_price: or_exit(args.amount("min-price")),
+                max_price: or_exit(args.amount("max-price")),
+                sort,
+                reverse: args.flag("reverse"),
+                limit: or_exit(args.number("limit")),
+                offset: or_exit(args.number("offset")).unwrap_or(0),
+                category: args.value("category").map(ProductType::from_string),
+            };
+            exec_list(&store, &options, &currency);
+        }
+        Some("report") => {
+            let options = ReportOptions {
+                from: or_exit(args.date("from")),
+                to: or_exit(args.date("to")),
+                top: or_exit(args.number("top")),
+                units: args.flag("units"),
+                chart: args
+                    .flag("chart")
+                    .then(|| or_exit(args.number("chart-width")).unwrap_or(40)),
+                adjust_to: or_exit(args.number("adjust-to")).map(|year: i32| {
+                    let file_name: &str = args.value("inflation").unwrap_or(DEFAULT_INFLATION_FILE);
+                    let index: HashMap<i32, i64> = or_exit(read_inflation_index(file_name));
+                    if !index.contains_key(&year) {
+                        eprintln!("{} has no index for {}", file_name, year);
+                        std::process::exit(1);
+                    }
+                    (year, index)
+                }),
+                json: args.flag("json"),
+                expand_other: or_exit(args.number("expand-other")).unwrap_or(DEFAULT_EXPAND_OTHER),
+            };
+            let mut out: Box<dyn Write> = or_exit(output_from_args(&args));
+            or_exit_write(exec_bucket_comparison(
+                &store,
+                &options,
+                !args.flag("no-cache"),
+                &currency,
+                &mut out,
+            ));
+        }
+        #[cfg(feature = "tui")]
+        Some("browse") => {
+            if let Some(purchases) = load_purchases(&store) {
+                or_exit(
+                    tui::browse(&purchases, &currency)
+                        .map_err(|error| format!("could not run the browser: {}", error)),
+                );
+            }
+        }
+        #[cfg(not(feature = "tui"))]
+        Some("browse") => {
+            eprintln!("browse is not available, build fima with --features tui");
+            std::process::exit(1);
+        }
+        Some("rename-category") => match args.positional.as_slice() {
+            [old, new] => exec_rename_category(&store, old, new),
+            _ => {
+                eprintln!(
+                    "rename-category requires the old and the new name\n\n{}",
+                    USAGE
+                );
+                std::process::exit(1);
+            }
+        },
+        Some("migrate") => match args.positional.first() {
+            Some(source) => exec_migrate(&store, source),
+            None => {
+                eprintln!("migrate requires the text file to copy from\n\n{}", USAGE);
+                std::process::exit(1);
+            }
+        },
+        Some("export-csv") => match args.positional.first() {
+            Some(output) => exec_export_csv(&store, output),
+            None => {
+                eprintln!("export-csv requires an output path\n\n{}", USAGE);
+                std::process::exit(1);
+            }
+        },
+        Some("stats") => {
+            let mut out: Box<dyn Write> = or_exit(output_from_args(&args));
+            let category: Option<ProductType> =
+                args.value("category").map(ProductType::from_string);
+            let format: OutputFormat = match args.value("output-format") {
+                None | Some("text") => OutputFormat::Text,
+                Some("json") => OutputFormat::Json,
+                Some("csv") => OutputFormat::Csv,
+                Some(other) => or_exit(Err(format!(
+                    "unknown output format '{}', expected text, json or csv",
+                    other
+                ))),
+            };
+            or_exit_write(exec_stats(
+                &store,
+                category.as_ref(),
+                format,
+                &currency,
+                &mut out,
+            ));
+        }
+        Some("monthly") => {
+            let budgets_file: &str = args.value("budgets").unwrap_or(DEFAULT_BUDGETS_FILE);
+            let budgets: HashMap<ProductType, i64> = or_exit(read_budgets(budgets_file));
+            let mut out: Box<dyn Write> = or_exit(output_from_args(&args));
+            or_exit_write(exec_monthly(&store, &budgets, &currency, &mut out));
+        }
+        Some("target") => {
+            let target_file: &str = args.value("target-file").unwrap_or(DEFAULT_TARGET_FILE);
+            let amount: Option<&str> = args.positional.first().map(String::as_str);
+            exec_target(&store, target_file, amount, &currency);
+        }
+        Some("income") => {
+            let income_file: &str = args.value("income").unwrap_or(DEFAULT_INCOME_FILE);
+            let amount: Option<&str> = args.positional.first().map(String::as_str);
+            let source: String = args
+                .positional
+                .iter()
+                .skip(1)
+                .cloned()
+                .collect::<Vec<_>>()
+                .join(" ");
+            exec_income(income_file, amount, &source, &currency);
+        }
+        Some("balance") => {
+            let income_file: &str = args.value("income").unwrap_or(DEFAULT_INCOME_FILE);
+            let monthly: bool = match args.positional.first().map(String::as_str) {
+                None => false,
+                Some("monthly") => true,
+                Some(other) => {
+                    eprintln!("unknown balance mode '{}'\n\n{}", other, USAGE);
+                    std::process::exit(1);
+                }
+            };
+            exec_balance(&store, income_file, monthly, &currency);
+        }
+        Some("goal") => {
+            let goals_file: &str = args.value("goals").unwrap_or(DEFAULT_GOALS_FILE);
+            let new_goal: Option<Goal> = match args.positional.as_slice() {
+                [] => None,
+                [name, cap] => {
+                    let Some(Ok(cap_cents)) = parse_cents(cap).map(check_price) else {
+                        eprintln!("the cap must be a non-negative amount like 2000.00");
+                        std::process::exit(1);
+                    };
+                    // the current month unless a period is given
+                    let today: NaiveDate = Local::now().date_naive();
+                    let first: NaiveDate = today.with_day(1).unwrap();
+                    let last: NaiveDate = first
+                        .checked_add_months(Months::new(1))
+                        .unwrap()
+                        .pred_opt()
+                        .unwrap();
+                    let from: NaiveDate = or_exit(args.date("from")).unwrap_or(first);
+                    let to: NaiveDate = or_exit(args.date("to")).unwrap_or(last);
+                    if from > to {
+                        eprintln!("--from must not be after --to");
+                        std::process::exit(1);
+                    }
+                    Some(Goal {
+                        name: name.trim().to_string(),
+                        from,
+                        to,
+                        cap_cents,
+                    })
+                }
+                _ => {
+                    eprintln!("goal needs a NAME and a CAP, or nothing\n\n{}", USAGE);
+                    std::process::exit(1);
+                }
+            };
+            exec_goal(&store, goals_file, new_goal, &currency);
+        }
+        Some("velocity") => {
+            let threshold: f64 =
+                or_exit(args.number("threshold")).unwrap_or(DEFAULT_VELOCITY_THRESHOLD);
+            exec_velocity(&store, threshold, &currency);
+        }
+        Some("projected") => exec_projected(&store, &currency),
+        Some("compare-periods") => {
+            let (from, to) = (or_exit(args.dates("from")), or_exit(args.dates("to")));
+            let ([from_a, from_b], [to_a, to_b]) = (from.as_slice(), to.as_slice()) else {
+                eprintln!(
+                    "compare-periods requires two --from and two --to dates\n\n{}",
+                    USAGE
+                );
+                std::process::exit(1);
+            };
+            exec_compare_periods(&store, (*from_a, *to_a), (*from_b, *to_b), &currency);
+        }
+        Some("recent") => {
+            let days: u32 = or_exit(args.number("days")).unwrap_or(30);
+            exec_recent(&store, days, &currency);
+        }
+        Some("categories") => exec_categories(&store),
+        Some("weekly") => exec_weekly(&store, &currency),
+        Some("what-if") => {
+            let excluded: Vec<ProductType> = args
+                .values("exclude")
+                .into_iter()
+                .flat_map(|categories| categories.split(','))
+                .filter(|category| !category.trim().is_empty())
+                .map(ProductType::from_string)
+                .collect();
+            if excluded.is_empty() {
+                eprintln!("what-if needs at least one --exclude CATEGORY\n\n{}", USAGE);
+                std::process::exit(1);
+            }
+            exec_what_if(&store, &excluded, &currency);
+        }
+        Some("quarterly") => exec_quarterly(&store, args.flag("by-category"), &currency),
+        Some("typical") => exec_typical(&store),
+        Some("drilldown") => {
+            let Some(category) = args.positional.first() else {
+                eprintln!("drilldown needs a CATEGORY\n\n{}", USAGE);
+                std::process::exit(1);
+            };
+            exec_drilldown(&store, &ProductType::from_string(category), &currency);
+        }
+        Some("frequent") => {
+            let by_value: bool = match args.value("by") {
+                None | Some("quantity") => false,
+                Some("value") => true,
+                Some(other) => or_exit(Err(format!(
+                    "unknown ranking '{}', expected quantity or value",
+                    other
+                ))),
+            };
+            let top: usize = or_exit(args.number("top")).unwrap_or(10);
+            exec_frequent(&store, top, by_value, &currency);
+        }
+        Some("cumulative") => {
+            let chart: Option<usize> = args
+                .flag("chart")
+                .then(|| or_exit(args.number("chart-width")).unwrap_or(40));
+            exec_cumulative(&store, chart, &currency);
+        }
+        Some("days") => {
+            let top: usize = or_exit(args.number("top")).unwrap_or(5);
+            exec_days(&store, top, &currency);
+        }
+        Some("weekdays") => {
+            let chart: Option<usize> = args
+                .flag("chart")
+                .then(|| or_exit(args.number("chart-width")).unwrap_or(40));
+            exec_weekdays(&store, chart, &currency);
+        }
+        Some("histogram") => {
+            let edges: Vec<i64> = match args.value("buckets") {
+                Some(edges) => or_exit(parse_edges(edges)),
+                None => DEFAULT_HISTOGRAM_EDGES.to_vec(),
+            };
+            let chart: Option<usize> = args
+                .flag("chart")
+                .then(|| or_exit(args.number("chart-width")).unwrap_or(40));
+            exec_histogram(&store, &edges, chart, &currency);
+        }
+        Some("trend") => exec_trend(&store, &currency),
+        Some("yearly") => exec_yearly(&store, &currency),
+        Some("by-tag") => {
+            let tag: Option<String> =
+                (!args.positional.is_empty()).then(|| args.positional.join(" "));
+            exec_by_tag(&store, tag.as_deref(), &currency);
+        }
+        Some("search") => {
+            let min_price: Option<i64> = or_exit(args.amount("min-price"));
+            let max_price: Option<i64> = or_exit(args.amount("max-price"));
+            let query: String = args.positional.join(" ");
+            exec_search(&store, &query, min_price, max_price, &currency);
+        }
+        Some(command @ ("max" | "min")) => {
+            let from: Option<NaiveDate> = or_exit(args.date("from"));
+            let to: Option<NaiveDate> = or_exit(args.date("to"));
+            exec_extreme(&store, from, to, command == "max", &currency);
+        }
+        Some("burn-rate") => exec_burn_rate(&store, &currency),
+        Some("average") => {
+            let time_frame: TimeFrame = match args.positional.first().map(String::as_str) {
+                None | Some("week") => TimeFrame::Week,
+                Some("month") => TimeFrame::Month,
+                Some("year") => TimeFrame::Year,
+                Some(other) => {
+                    eprintln!("unknown time frame '{}'\n\n{}", other, USAGE);
+                    std::process::exit(1);
+                }
+            };
+            compute_average_expenses(&store, time_frame, &currency);
+        }
+        None => println!("{}", USAGE),
+        Some(other) => {
+            eprintln!("unknown command '{}'\n\n{}", other, USAGE);
+            std::process::exit(1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn purchase(
+        name: &str,
+        price_cents: i64,
+        product_type: &str,
+        quantity: u32,
+        date: &str,
+    ) -> Purchase {
+        let product = Product::new(
+            name.to_string(),
+            price_cents,
+            ProductType::from_string(product_type),
+            String::from("brand"),
+        )
+        .unwrap();
+        Purchase::new(
+            product,
+            Quantity {
+                milli: quantity as u64 * 1000,
+            },
+            date.parse().unwrap(),
+            String::from("shop"),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn write_then_read_round_trips() {
+        let purchases = vec![
+            purchase("apple", 150, "food", 3, "2024-01-02"),
+            purchase("laptop", 129999, "technology", 1, "2024-02-29"),
+            purchase("rent", 80000, "rent", 1, "2024-03-01"),
+        ];
+        let mut buffer: Vec<u8> = Vec::new();
+        write_purchases(&purchases, &mut buffer).unwrap();
+        let read: Vec<Purchase> = read_purchases(Cursor::new(buffer)).unwrap();
+        assert_eq!(read, purchases);
+    }
+
+    #[test]
+    fn round_trips_unusual_characters() {
+        let purchases = vec![purchase(
+            "crème brûlée l'été ☕ 50%",
+            499,
+            "food",
+            2,
+            "2023-12-31",
+        )];
+        let mut buffer: Vec<u8> = Vec::new();
+        write_purchases(&purchases, &mut buffer).unwrap();
+        let read: Vec<Purchase> = read_purchases(Cursor::new(buffer)).unwrap();
+        assert_eq!(read, purchases);
+    }
+
+    #[test]
+    fn writes_no_trailing_newline() {
+        let purchases = vec![
+            purchase("apple", 150, "food", 3, "2024-01-02"),
+            purchase("pear", 99, "food", 1, "2024-01-03"),
+        ];
+        let mut buffer: Vec<u8> = Vec::new();
+        write_purchases(&purchases, &mut buffer).unwrap();
+        assert_eq!(
+            String::from_utf8(buffer).unwrap(),
+            "apple, 1.50, food, brand, 3, 2024-01-02, shop,\npear, 0.99, food, brand, 1, 2024-01-03, shop,"
+        );
+    }
+
+    #[test]
+    fn empty_input_reads_no_purchases() {
+        let mut buffer: Vec<u8> = Vec::new();
+        write_purchases(&[], &mut buffer).unwrap();
+        assert!(buffer.is_empty());
+        assert!(read_purchases(Cursor::new(buffer)).unwrap().is_empty());
+    }
+
+    #[test]
+    fn bucket_stats_average_per_purchase() {
+        let purchases = [
+            purchase("apple", 100, "food", 1, "2024-01-02"),
+            purchase("pear", 100, "food", 1, "2024-01-03"),
+            purchase("bread", 100, "food", 1, "2024-01-04"),
+        ];
+        let bucket = Bucket::new(ProductType::Food, purchases.iter().collect());
+        let stats: BucketStats = eval_bucket_stats(&bucket);
+        assert_eq!((stats.total, stats.count, stats.average), (300, 3, 100));
+        let empty = Bucket::new(ProductType::Food, Vec::new());
+        let stats: BucketStats = eval_bucket_stats(&empty);
+        assert_eq!((stats.total, stats.count, stats.average), (0, 0, 0));
+    }
+
+    #[test]
+    fn round_trips_fields_needing_quotes() {
+        let mut purchases = vec![
+            purchase("coffee, beans", 899, "food", 1, "2024-01-02"),
+            purchase("the \"good\" tea", 450, "food", 2, "2024-01-03"),
+        ];
+        purchases[1].shop = String::from("  corner shop ");
+        purchases[1].product.brand = String::from("\"");
+        let mut buffer: Vec<u8> = Vec::new();
+        write_purchases(&purchases, &mut buffer).unwrap();
+        let read: Vec<Purchase> = read_purchases(Cursor::new(buffer)).unwrap();
+        assert_eq!(read, purchases);
+        assert_eq!(read[1].product.brand, "\"");
+    }
+
+    #[test]
+    fn reads_old_unquoted_lines() {
+        let contents = "apple, 1.50, food, brand, 3, 2024-01-02, shop";
+        let read: Vec<Purchase> = read_purchases(Cursor::new(contents)).unwrap();
+        assert_eq!(read, vec![purchase("apple", 150, "food", 3, "2024-01-02")]);
+    }
+
+    #[test]
+    fn rejects_unterminated_quote() {
+        let contents = "\"apple, 1.50, food, brand, 3, 2024-01-02, shop";
+        assert!(matches!(
+            read_purchases(Cursor::new(contents)),
+            Err(ReadError::BadQuoting { line: 1 })
+        ));
+    }
+
+    #[test]
+    fn finds_duplicates_by_name_price_quantity_and_date() {
+        let purchases = [
+            purchase("apple", 150, "food", 3, "2024-01-02"),
+            purchase("apple", 150, "food", 3, "2024-01-03"),
+            purchase("apple", 150, "food", 3, "2024-01-02"),
+            purchase("apple", 150, "food", 2, "2024-01-02"),
+            purchase("apple", 150, "food", 3, "2024-01-02"),
+        ];
+        assert_eq!(find_duplicates(&purchases), vec![(0, 2), (0, 4)]);
+    }
+
+    #[test]
+    fn exports_csv_with_header_and_value() {
+        let purchases = [
+            purchase("coffee, beans", 899, "food", 2, "2024-01-02"),
+            purchase("rent", 80000, "rent", 1, "2024-01-03"),
+        ];
+        let mut buffer: Vec<u8> = Vec::new();
+        write_csv(&purchases, &mut buffer).unwrap();
+        assert_eq!(
+            String::from_utf8(buffer).unwrap(),
+            "name,price,type,quantity,date,value\n\
+             \"coffee, beans\",8.99,food,2,2024-01-02,17.98\n\
+             rent,800.00,rent,1,2024-01-03,800.00\n"
+        );
+    }
+
+    #[test]
+    fn parses_dates_in_any_accepted_format() {
+        let expected: NaiveDate = NaiveDate::from_ymd_opt(2024, 3, 15).unwrap();
+        for input in ["2024-03-15", "15-03-2024", "15.03.2024", "03/15/2024"] {
+            assert_eq!(parse_date(input), Some(expected));
+        }
+        assert_eq!(parse_date("15/03/2024"), None);
+    }
+
+    #[test]
+    fn expands_recurring_purchase_clamping_month_ends() {
+        let base = purchase("gym", 2999, "health", 1, "2024-01-31");
+        let dates: Vec<String> = expand_recurring(&base, 4)
+            .iter()
+            .map(|purchase| purchase.date.to_string())
+            .collect();
+        assert_eq!(
+            dates,
+            ["2024-01-31", "2024-02-29", "2024-03-31", "2024-04-30"]
+        );
+    }
+
+    #[test]
+    fn products_compare_on_name_price_and_type() {
+        let apple = purchase("apple", 150, "food", 1, "2024-01-02").product;
+        let mut other_brand = apple.clone();
+        other_brand.brand = String::from("other");
+        assert_eq!(apple, other_brand);
+        let mut quantities: HashMap<Product, u32> = HashMap::new();
+        *quantities.entry(apple.clone()).or_insert(0) += 1;
+        *quantities.entry(other_brand).or_insert(0) += 2;
+        assert_eq!(quantities.get(&apple), Some(&3));
+        let mut pricier = apple.clone();
+        pricier.price_cents = 151;
+        assert_ne!(apple, pricier);
+        let mut other_type = apple.clone();
+        other_type.product_type = ProductType::Other;
+        assert_ne!(apple, other_type);
+    }
+
+    #[test]
+    fn round_trips_tags() {
+        let purchases = vec![
+            purchase("lunch", 1250, "food", 1, "2024-01-02").with_tags(&["Work", " reimbursable "]),
+            purchase("pear", 99, "food", 1, "2024-01-03"),
+            purchase("flowers", 2000, "presents", 1, "2024-01-04").with_tags(&["gift", "day trip"]),
+        ];
+        let mut buffer: Vec<u8> = Vec::new();
+        write_purchases(&purchases, &mut buffer).unwrap();
+        let read: Vec<Purchase> = read_purchases(Cursor::new(buffer)).unwrap();
+        assert_eq!(read, purchases);
+        assert_eq!(read[0].tags, ["work", "reimbursable"]);
+        assert_eq!(read[2].tags, ["gift", "day-trip"]);
+        let totals: BTreeMap<String, i64> = tag_totals(&read);
+        assert_eq!(totals.get("work"), Some(&1250));
+        assert_eq!(totals.get("gift"), Some(&2000));
+    }
+
+    #[test]
+    fn import_skips_bad_lines() {
+        let input: &str = "apple, 1.50, food, brand, 3, 2024-01-02, shop\n\npear, 1,5, food\nkiwi, 0.40, food, brand, 0, 2024-01-03, shop\nplum, 0.30, food, brand, 2, 2024-01-04, shop";
+        let (purchases, skipped) = import_purchases(Cursor::new(input)).unwrap();
+        assert_eq!(
+            purchases,
+            [
+                purchase("apple", 150, "food", 3, "2024-01-02"),
+                purchase("plum", 30, "food", 2, "2024-01-04"),
+            ]
+        );
+        assert!(matches!(skipped[0], ReadError::MissingField { line: 3 }));
+        assert!(matches!(skipped[1], ReadError::Invalid { line: 4, .. }));
+        assert_eq!(skipped.len(), 2);
+    }
+
+    #[test]
+    fn daily_rate_spans_inclusive_days() {
+        assert_eq!(daily_rate(Vec::new()), None);
+        let same_day = vec![
+            purchase("apple", 150, "food", 2, "2024-01-02"),
+            purchase("pear", 100, "food", 1, "2024-01-02"),
+        ];
+        assert_eq!(daily_rate(same_day), Some((400.0, 1)));
+        let purchases = vec![
+            purchase("apple", 500, "food", 1, "2024-01-10"),
+            purchase("pear", 500, "food", 1, "2024-01-01"),
+        ];
+        assert_eq!(daily_rate(purchases), Some((100.0, 10)));
+    }
+
+    #[test]
+    fn bars_scale_to_largest_value() {
+        assert_eq!(bar(1000, 1000, 40).chars().count(), 40);
+        assert_eq!(bar(250, 1000, 40).chars().count(), 10);
+        assert_eq!(bar(0, 1000, 40), "");
+    }
+
+    #[test]
+    fn names_keep_casing_but_match_ignoring_it() {
+        let purchases = vec![
+            purchase(" iPhone ", 99900, "technology", 1, "2024-01-02"),
+            purchase("IPHONE", 99900, "technology", 1, "2024-01-02"),
+        ];
+        assert_eq!(purchases[0].product.name, "iPhone");
+        assert_eq!(purchases[0].product, purchases[1].product);
+        assert_eq!(find_duplicates(&purchases), [(0, 1)]);
+        assert_eq!(search_purchases(&purchases, "IphOne").len(), 2);
+    }
+
+    #[test]
+    fn report_writes_to_given_output() {
+        let purchases = [
+            purchase("apple", 150, "food", 2, "2024-01-02"),
+            purchase("ticket", 900, "travel", 1, "2024-01-03"),
+        ];
+        let refs: Vec<&Purchase> = purchases.iter().collect();
+        let options = ReportOptions {
+            from: None,
+            to: None,
+            top: None,
+            units: false,
+            chart: None,
+            adjust_to: None,
+            json: false,
+            expand_other: DEFAULT_EXPAND_OTHER,
+        };
+        let mut out: Vec<u8> = Vec::new();
+        compare_buckets(
+            sort_type_buckets(&refs),
+            &options,
+            &Currency::default(),
+            &mut out,
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "travel: $9.00 (75.0%), 1 purchases, avg $9.00\nfood: $3.00 (25.0%), 1 purchases, avg $3.00\nTotal: $12.00\n"
+        );
+    }
+
+    #[test]
+    fn extreme_purchase_breaks_ties_by_date() {
+        let purchases = [
+            purchase("later", 300, "food", 1, "2024-01-05"),
+            purchase("small", 100, "food", 1, "2024-01-04"),
+            purchase("earlier", 150, "food", 2, "2024-01-02"),
+        ];
+        let refs: Vec<&Purchase> = purchases.iter().collect();
+        assert_eq!(extreme_purchase(&refs, true), Some(&purchases[2]));
+        assert_eq!(extreme_purchase(&refs, false), Some(&purchases[1]));
+        assert_eq!(extreme_purchase(&[], true), None);
+    }
+
+    #[test]
+    fn adjusts_prices_to_reference_year() {
+        let purchases = [
+            purchase("bread", 200, "food", 2, "2019-05-01"),
+            purchase("milk", 100, "food", 1, "2024-05-01"),
+            purchase("tea", 300, "food", 1, "2010-05-01"),
+        ];
+        let index: HashMap<i32, i64> = HashMap::from([(2019, 10000), (2024, 12000)]);
+        let (adjusted, missing) = adjust_for_inflation(&purchases, 2024, &index);
+        assert_eq!(adjusted[0].value(), 480);
+        assert_eq!(adjusted[1].value(), 100);
+        assert_eq!(adjusted[2].value(), 300);
+        assert_eq!(missing, [2010]);
+    }
+
+    #[test]
+    fn monthly_trend_fills_gaps() {
+        let totals: BTreeMap<(i32, u32), i64> =
+            BTreeMap::from([((2023, 11), 500), ((2024, 2), 300), ((2024, 3), 450)]);
+        assert_eq!(
+            monthly_trend(&totals),
+            [
+                ((2023, 11), 500, None),
+                ((2023, 12), 0, Some(-500)),
+                ((2024, 1), 0, Some(0)),
+                ((2024, 2), 300, Some(300)),
+                ((2024, 3), 450, Some(150)),
+            ]
+        );
+    }
+
+    #[test]
+    fn resolves_category_aliases() {
+        assert_eq!(ProductType::from_string("Groceries"), ProductType::Food);
+        assert_eq!(ProductType::from_string(" tech "), ProductType::Technology);
+        assert_eq!(
+            ProductType::from_string("rent"),
+            ProductType::Custom(String::from("rent"))
+        );
+    }
+
+    #[test]
+    fn weekly_totals_use_iso_years() {
+        let purchases = [
+            purchase("apple", 100, "food", 1, "2024-12-29"),
+            purchase("pear", 200, "food", 1, "2024-12-30"),
+            purchase("plum", 300, "food", 1, "2025-01-05"),
+        ];
+        assert_eq!(
+            weekly_totals(&purchases),
+            BTreeMap::from([((2024, 52), 100), ((2025, 1), 500)])
+        );
+    }
+
+    #[test]
+    fn weekday_totals_start_on_monday() {
+        let purchases = [
+            purchase("apple", 100, "food", 1, "2024-01-01"),
+            purchase("pear", 200, "food", 2, "2024-01-07"),
+            purchase("plum", 300, "food", 1, "2024-01-08"),
+        ];
+        assert_eq!(weekday_totals(&purchases), [400, 0, 0, 0, 0, 0, 400]);
+        let first: NaiveDate = "2024-01-01".parse().unwrap();
+        let last: NaiveDate = "2024-01-08".parse().unwrap();
+        assert_eq!(weekday_counts(first, last), [2, 1, 1, 1, 1, 1, 1]);
+    }
+
+    #[test]
+    fn report_json_is_valid_without_purchases() {
+        let purchases = [
+            purchase("apple", 150, "food", 2, "2024-01-02"),
+            purchase("ticket", 905, "travel", 1, "2024-01-03"),
+        ];
+        let refs: Vec<&Purchase> = purchases.iter().collect();
+        let ranked: Vec<CategorySummary> = sort_type_buckets(&refs)
+            .iter()
+            .map(summarize_bucket)
+            .collect();
+        let json: serde_json::Value =
+            serde_json::from_str(&report_json(&ranked, &Currency::default())).unwrap();
+        assert_eq!(json["total"], 12.05);
+        assert_eq!(json["categories"]["travel"], 9.05);
+        assert_eq!(json["currency"], "USD");
+        let euro: Currency = Currency::from_locale("de-DE").unwrap();
+        let json: serde_json::Value = serde_json::from_str(&report_json(&ranked, &euro)).unwrap();
+        assert_eq!(json["currency"], "EUR");
+        let empty: serde_json::Value =
+            serde_json::from_str(&report_json(&[], &Currency::default())).unwrap();
+        assert_eq!(empty["categories"], serde_json::json!({}));
+    }
+
+    #[test]
+    fn category_filter_rejects_unknown_custom_categories() {
+        let purchases = [
+            purchase("apple", 150, "food", 2, "2024-01-02"),
+            purchase("rent", 90000, "housing", 1, "2024-01-03"),
+        ];
+        let housing = ProductType::from_string("housing");
+        assert_eq!(
+            filter_by_category(&purchases, &housing).unwrap(),
+            [&purchases[1]]
+        );
+        assert!(filter_by_category(&purchases, &ProductType::Style)
+            .unwrap()
+            .is_empty());
+        let error: String =
+            filter_by_category(&purchases, &ProductType::from_string("fod")).unwrap_err();
+        assert!(error.contains("food"));
+        assert!(error.contains("housing"));
+    }
+
+    #[test]
+    fn appends_separate_lines_by_exactly_one_newline() {
+        let path = std::env::temp_dir().join(format!("fima-newline-{}.txt", std::process::id()));
+        let file_name: String = path.to_string_lossy().into_owned();
+        let apple: Purchase = purchase("apple", 100, "food", 1, "2024-01-02");
+        let line: String = purchase_to_line(&apple);
+        let cases = [
+            ("", line.clone()),
+            ("old\n", format!("old\n{}", line)),
+            ("old", format!("old\n{}", line)),
+            ("old\r\n", format!("old\r\n{}", line)),
+            (" \t ", format!(" \t \n{}", line)),
+        ];
+        for (contents, expected) in cases {
+            std::fs::write(&path, contents).unwrap();
+            write_to_file(&apple, &file_name).unwrap();
+            assert_eq!(std::fs::read_to_string(&path).unwrap(), expected);
+        }
+        // whitespace before the purchase is skipped when reading
+        assert_eq!(read_from_file(&file_name).unwrap(), [apple]);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn concurrent_appends_keep_every_purchase() {
+        let path = std::env::temp_dir().join(format!("fima-append-{}.txt", std::process::id()));
+        let file_name: String = path.to_string_lossy().into_owned();
+        let _ = std::fs::remove_file(&path);
+        let writers: Vec<std::thread::JoinHandle<()>> = ["apple", "pear"]
+            .into_iter()
+            .map(|name| {
+                let file_name: String = file_name.clone();
+                std::thread::spawn(move || {
+                    for _ in 0..50 {
+                        write_to_file(&purchase(name, 100, "food", 1, "2024-01-02"), &file_name)
+                            .unwrap();
+                    }
+                })
+            })
+            .collect();
+        for writer in writers {
+            writer.join().unwrap();
+        }
+        let purchases: Vec<Purchase> = read_from_file(&file_name).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(purchases.len(), 100);
+        let apples: usize = purchases
+            .iter()
+            .filter(|purchase| purchase.product.name == "apple")
+            .count();
+        assert_eq!(apples, 50);
+    }
+
+    #[test]
+    fn concurrent_edits_and_deletes_keep_appended_purchases() {
+        let path = std::env::temp_dir().join(format!("fima-modify-{}.txt", std::process::id()));
+        let file_name: String = path.to_string_lossy().into_owned();
+        let store = |file_name: String| Store {
+            file_name,
+            format: Format::Text,
+            merged: Vec::new(),
+        };
+        let _ = std::fs::remove_file(&path);
+        let old: Vec<Purchase> = (0..51)
+            .map(|_| purchase("old", 100, "food", 1, "2024-01-01"))
+            .collect();
+        store(file_name.clone()).write_all(&old).unwrap();
+        let appender: std::thread::JoinHandle<()> = {
+            let store: Store = store(file_name.clone());
+            std::thread::spawn(move || {
+                for _ in 0..50 {
+                    store
+                        .append(&purchase("pear", 100, "food", 1, "2024-01-02"))
+                        .unwrap();
+                }
+            })
+        };
+        // appends only go to the end, so the first purchase is always an old one
+        let store: Store = store(file_name);
+        for _ in 0..50 {
+            edit_purchase(&store, 0, purchase("edited", 200, "food", 1, "2024-01-01")).unwrap();
+            assert_eq!(delete_purchase(&store, 0).unwrap().product.name, "edited");
+        }
+        appender.join().unwrap();
+        let purchases: Vec<Purchase> = store.read().unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(purchases.len(), 51);
+        assert_eq!(purchases[0].product.name, "old");
+        let pears: usize = purchases
+            .iter()
+            .filter(|purchase| purchase.product.name == "pear")
+            .count();
+        assert_eq!(pears, 50);
+    }
+
+    #[test]
+    fn month_progress_counts_current_month_until_today() {
+        let purchases = [
+            purchase("apple", 100, "food", 1, "2024-01-31"),
+            purchase("pear", 200, "food", 2, "2024-02-01"),
+            purchase("plum", 300, "food", 1, "2024-02-10"),
+            purchase("later", 500, "food", 1, "2024-02-20"),
+        ];
+        let today: NaiveDate = "2024-02-10".parse().unwrap();
+        assert_eq!(month_progress(&purchases, today), (700, 10, 29));
+    }
+
+    #[test]
+    fn suggests_close_categories() {
+        assert_eq!(edit_distance("technolgy", "technology"), 1);
+        assert_eq!(edit_distance("", "food"), 4);
+        assert_eq!(suggest_category("Technolgy"), Some("technology"));
+        assert_eq!(suggest_category("fod"), Some("food"));
+        assert_eq!(suggest_category("food"), None);
+        assert_eq!(suggest_category("housing"), None);
+    }
+
+    #[test]
+    fn compares_two_periods_per_category() {
+        let purchases = [
+            purchase("apple", 100, "food", 2, "2024-01-05"),
+            purchase("ticket", 900, "travel", 1, "2024-01-20"),
+            purchase("pear", 150, "food", 1, "2024-02-03"),
+            purchase("shirt", 2000, "style", 1, "2024-02-10"),
+        ];
+        let date = |date: &str| date.parse::<NaiveDate>().unwrap();
+        let january = (date("2024-01-01"), date("2024-01-31"));
+        let february = (date("2024-02-01"), date("2024-02-29"));
+        assert_eq!(
+            compare_periods(&purchases, january, february),
+            [
+                (ProductType::Food, 200, 150),
+                (ProductType::Style, 0, 2000),
+                (ProductType::Travel, 900, 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn skips_blank_and_truncated_lines() {
+        let input: &str = "\napple, 1.50, food, brand, 3, 2024-01-02, shop\n   \npear, 0.99, food\n\nplum, 0.30, food, brand, 2, 2024-01-04, shop\n\n";
+        assert_eq!(
+            read_purchases(Cursor::new(input)).unwrap(),
+            [
+                purchase("apple", 150, "food", 3, "2024-01-02"),
+                purchase("plum", 30, "food", 2, "2024-01-04"),
+            ]
+        );
+    }
+
+    #[test]
+    fn merged_store_concatenates_files_skipping_missing_ones() {
+        let dir = std::env::temp_dir();
+        let file = |name: &str| {
+            let path = dir.join(format!("fima-merge-{}-{}.txt", std::process::id(), name));
+            path.to_string_lossy().into_owned()
+        };
+        let (first, second, missing) = (file("first"), file("second"), file("missing"));
+        write_all_to_file(&[purchase("apple", 150, "food", 1, "2024-01-02")], &first).unwrap();
+        write_all_to_file(&[purchase("pear", 99, "food", 2, "2024-01-03")], &second).unwrap();
+        let store = Store {
+            file_name: first.clone(),
+            format: Format::Text,
+            merged: vec![missing, second.clone()],
+        };
+        let purchases: Vec<Purchase> = store.read().unwrap();
+        std::fs::remove_file(&first).unwrap();
+        std::fs::remove_file(&second).unwrap();
+        assert_eq!(
+            purchases,
+            [
+                purchase("apple", 150, "food", 1, "2024-01-02"),
+                purchase("pear", 99, "food", 2, "2024-01-03"),
+            ]
+        );
+    }
+
+    #[test]
+    fn fractional_quantities_round_trip() {
+        let line: &str = "Cheese, 12.99, food, deli, 1.5, 2024-03-01, market,";
+        let cheese: Purchase = parse_line(line, 1).unwrap();
+        assert_eq!(cheese.quantity.to_string(), "1.5");
+        // 19.485 rounds half to even
+        assert_eq!(cheese.value(), 1948);
+        assert_eq!(purchase_to_line(&cheese), line);
+        let whole: Purchase = purchase("apple", 150, "food", 3, "2024-01-02");
+        assert!(purchase_to_line(&whole).contains(", 3, "));
+        let json: String = serde_json::to_string(&[&whole, &cheese]).unwrap();
+        assert!(json.contains("\"quantity\":3,") && json.contains("\"quantity\":1.5,"));
+        let read: Vec<Purchase> = serde_json::from_str(&json).unwrap();
+        assert_eq!(read, [whole, cheese]);
+        assert!(parse_line("Cheese, 1, food, deli, 1.2.3, 2024-03-01, market", 1).is_err());
+    }
+
+    #[test]
+    fn histogram_puts_boundary_values_in_the_higher_range() {
+        let purchases = [
+            purchase("gum", 99, "food", 1, "2024-01-02"),
+            purchase("book", 1000, "education", 1, "2024-01-03"),
+            purchase("shoes", 2500, "style", 2, "2024-01-04"),
+            purchase("phone", 30000, "technology", 1, "2024-01-05"),
+        ];
+        let edges: Vec<i64> = parse_edges("10, 50,100").unwrap();
+        assert_eq!(edges, DEFAULT_HISTOGRAM_EDGES);
+        assert_eq!(
+            value_histogram(&purchases, &edges),
+            [(1, 99), (1, 1000), (1, 5000), (1, 30000)]
+        );
+        assert!(parse_edges("50,10").is_err());
+    }
+
+    #[test]
+    fn config_provides_defaults_for_options_not_given() {
+        let config: Args =
+            parse_config("currency = \"€\"\ntop = 5\nunits = true\n", "fima.toml").unwrap();
+        let args: Args = parse_args(["report", "--top", "3"].iter().map(|arg| arg.to_string()))
+            .unwrap()
+            .with_defaults(config);
+        assert_eq!(args.value("top"), Some("3"));
+        assert_eq!(args.value("currency"), Some("€"));
+        assert!(args.flag("units"));
+        assert!(parse_config("colour = \"red\"", "fima.toml").is_err());
+        assert!(parse_config("top = [5]", "fima.toml").is_err());
+        assert!(parse_config("top = ", "fima.toml").is_err());
+        let given = |args: &[&str]| {
+            let config: Args = parse_config("date-format = \"%d/%m/%Y\"", "fima.toml").unwrap();
+            parse_args(args.iter().map(|arg| arg.to_string()))
+                .unwrap()
+                .with_defaults(config)
+        };
+        assert_eq!(given(&["add"]).value("date-format"), Some("%d/%m/%Y"));
+        let args: Args = given(&["add", "--date-format", "%m/%d/%Y"]);
+        assert_eq!(args.value("date-format"), Some("%m/%d/%Y"));
+        assert_eq!(check_date_format("%d/%m/%Y"), Ok(String::from("%d/%m/%Y")));
+        assert!(check_date_format("%d/%m").is_err());
+        assert!(check_date_format("%Q").is_err());
+    }
+
+    #[test]
+    fn finds_the_biggest_category_changes() {
+        let before: HashMap<ProductType, i64> =
+            HashMap::from([(ProductType::Travel, 15000), (ProductType::Food, 10000)]);
+        let after: HashMap<ProductType, i64> = HashMap::from([
+            (ProductType::Travel, 27000),
+            (ProductType::Food, 8000),
+            (ProductType::Style, 500),
+        ]);
+        let (increase, decrease) = biggest_changes(&before, &after);
+        let increase: CategoryChange = increase.unwrap();
+        assert_eq!(
+            describe_change(&increase, &Currency::default()),
+            "travel +$120.00 (up 80.0%)"
+        );
+        assert_eq!(decrease, Some((ProductType::Food, 10000, 8000)));
+        assert_eq!(biggest_changes(&after, &after), (None, None));
+    }
+
+    #[test]
+    fn purchases_sort_by_date_then_value() {
+        let mut purchases = [
+            purchase("tea", 300, "food", 1, "2024-01-02"),
+            purchase("bread", 250, "food", 1, "2024-01-02"),
+            purchase("apple", 50, "food", 1, "2024-01-01"),
+        ];
+        purchases.sort();
+        let names: Vec<&str> = purchases
+            .iter()
+            .map(|purchase| purchase.product.name.as_str())
+            .collect();
+        assert_eq!(names, ["apple", "bread", "tea"]);
+        // equal purchases compare Equal even with different brands, like PartialEq
+        let mut other_brand: Purchase = purchases[0].clone();
+        other_brand.product.brand = String::from("other");
+        assert_eq!(other_brand, purchases[0]);
+        assert_eq!(other_brand.cmp(&purchases[0]), Ordering::Equal);
+        other_brand.shop = String::from("market");
+        assert_ne!(other_brand.cmp(&purchases[0]), Ordering::Equal);
+    }
+
+    #[test]
+    fn refunds_are_subtracted_and_round_trip() {
+        let line: &str = "apple, 1.50, food, brand, -2, 2024-01-02, shop,";
+        let refund: Purchase = parse_line(line, 1).unwrap();
+        assert!(refund.refund);
+        assert_eq!(refund.value(), -300);
+        assert_eq!(purchase_to_line(&refund), line);
+        let purchases = [purchase("apple", 150, "food", 4, "2024-01-01"), refund];
+        let refs: Vec<&Purchase> = purchases.iter().collect();
+        let bucket: &Bucket = &sort_type_buckets(&refs)[0];
+        let stats: BucketStats = eval_bucket_stats(bucket);
+        assert_eq!((stats.total, stats.refunded), (300, 300));
+        assert!(find_duplicates(&purchases).is_empty());
+    }
+
+    #[test]
+    fn renames_only_the_matching_category() {
+        let mut purchases = [
+            purchase("scarf", 2000, "presents", 1, "2024-01-02"),
+            purchase("apple", 150, "food", 1, "2024-01-03"),
+            purchase("mug", 800, "presents", 1, "2024-01-04"),
+        ];
+        let gifts: ProductType = ProductType::from_string("gifts");
+        assert_eq!(
+            rename_category(&mut purchases, &ProductType::Presents, &gifts),
+            2
+        );
+        assert_eq!(purchases[2].product.product_type, gifts);
+        assert_eq!(purchases[1].product.product_type, ProductType::Food);
+    }
+
+    #[test]
+    fn typical_month_leaves_out_the_current_month() {
+        let purchases = [
+            purchase("apple", 1000, "food", 1, "2024-01-10"),
+            purchase("pear", 2001, "food", 1, "2024-02-10"),
+            purchase("ticket", 900, "travel", 1, "2024-02-11"),
+            purchase("plum", 50000, "food", 1, "2024-03-01"),
+        ];
+        let today: NaiveDate = "2024-03-15".parse().unwrap();
+        let (averages, months) = typical_month(&purchases, today);
+        assert_eq!(months, 2);
+        assert_eq!(
+            averages,
+            [(ProductType::Food, 1500), (ProductType::Travel, 450)]
+        );
+    }
+
+    #[test]
+    fn reads_windows_line_endings() {
+        let contents: &str = "apple, 1.50, food, brand, 2, 2024-01-02, shop\r\n\
+                              \"pear\", 0.99, food, brand, 1, 2024-01-03, \"shop\", weekly\r\n\
+                              \r\n\
+                              plum, 0.30, food, brand, 1, 2024-01-04\r, shop\r\n";
+        let purchases: Vec<Purchase> = read_purchases(contents.as_bytes()).unwrap();
+        assert_eq!(purchases.len(), 3);
+        assert_eq!(purchases[0].shop, "shop");
+        assert_eq!(purchases[1].tags, ["weekly"]);
+        assert_eq!(
+            purchases[2].date,
+            "2024-01-04".parse::<NaiveDate>().unwrap()
+        );
+    }
+
+    #[test]
+    fn cumulative_totals_combine_purchases_of_a_day() {
+        let purchases = [
+            purchase("pear", 200, "food", 1, "2024-01-03"),
+            purchase("apple", 100, "food", 1, "2024-01-01"),
+            purchase("plum", 300, "food", 2, "2024-01-03"),
+        ];
+        let date = |date: &str| date.parse::<NaiveDate>().unwrap();
+        assert_eq!(
+            cumulative_totals(&purchases),
+            [(date("2024-01-01"), 100), (date("2024-01-03"), 900)]
+        );
+    }
+
+    #[test]
+    fn product_totals_group_names_ignoring_case() {
+        let purchases = [
+            purchase("Stamps", 40, "other", 1, "2024-01-02"),
+            purchase("battery", 300, "other", 1, "2024-01-03"),
+            purchase("stamps", 40, "other", 2, "2024-01-04"),
+        ];
+        let refs: Vec<&Purchase> = purchases.iter().collect();
+        let bucket: &Bucket = &sort_type_buckets(&refs)[0];
+        assert_eq!(
+            product_totals(bucket),
+            [
+                (String::from("battery"), 300),
+                (String::from("Stamps"), 120)
+            ]
+        );
+        let mut options = ReportOptions {
+            from: None,
+            to: None,
+            top: None,
+            units: false,
+            chart: None,
+            adjust_to: None,
+            json: false,
+            expand_other: DEFAULT_EXPAND_OTHER,
+        };
+        let mut out: Vec<u8> = Vec::new();
+        compare_buckets(
+            sort_type_buckets(&refs),
+            &options,
+            &Currency::default(),
+            &mut out,
+        )
+        .unwrap();
+        assert!(String::from_utf8(out)
+            .unwrap()
+            .contains("\n  battery: $3.00\n  Stamps: $1.20\n"));
+        options.expand_other = 100.1;
+        let mut out: Vec<u8> = Vec::new();
+        compare_buckets(
+            sort_type_buckets(&refs),
+            &options,
+            &Currency::default(),
+            &mut out,
+        )
+        .unwrap();
+        assert!(!String::from_utf8(out).unwrap().contains("battery"));
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn round_trips_through_compressed_file() {
+        let path = std::env::temp_dir().join(format!("fima-{}.txt.gz", std::process::id()));
+        let file_name: String = path.to_string_lossy().into_owned();
+        let _ = std::fs::remove_file(&path);
+        let purchases = [
+            purchase("apple", 150, "food", 2, "2024-01-02"),
+            purchase("ticket", 905, "travel", 1, "2024-01-03"),
+        ];
+        write_to_file(&purchases[0], &file_name).unwrap();
+        write_to_file(&purchases[1], &file_name).unwrap();
+        let contents: Vec<u8> = std::fs::read(&path).unwrap();
+        assert_eq!(contents[..2], [0x1f, 0x8b]);
+        assert_eq!(read_from_file(&file_name).unwrap(), purchases);
+        let streamed: Vec<Purchase> = read_purchases_iter(&file_name)
+            .collect::<Result<Vec<Purchase>, ReadError>>()
+            .unwrap();
+        assert_eq!(streamed, purchases);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn frequent_products_merge_names_ignoring_case() {
+        let purchases = [
+            purchase("Coffee", 400, "food", 1, "2024-01-02"),
+            purchase("tea", 300, "food", 2, "2024-01-03"),
+            purchase("coffee", 400, "food", 1, "2024-01-04"),
+            purchase("laptop", 90000, "technology", 1, "2024-01-05"),
+        ];
+        let names = |by_value: bool| -> Vec<String> {
+            frequent_products(&purchases, by_value)
+                .into_iter()
+                .map(|(name, _, _)| name)
+                .collect()
+        };
+        assert_eq!(names(false), ["Coffee", "tea", "laptop"]);
+        assert_eq!(names(true), ["laptop", "Coffee", "tea"]);
+        assert_eq!(frequent_products(&purchases, false)[0].2, 800);
+    }
+
+    #[test]
+    fn round_trips_notes_after_the_tags() {
+        let gift: Purchase =
+            purchase("scarf", 2000, "presents", 1, "2024-01-02").with_note("for Sam, birthday ");
+        let line: String = purchase_to_line(&gift);
+        assert!(line.ends_with("shop, , \"for Sam, birthday\""));
+        assert_eq!(parse_line(&line, 1).unwrap(), gift);
+        let tagged: Purchase = gift.clone().with_tags(&["family"]);
+        assert_eq!(parse_line(&purchase_to_line(&tagged), 1).unwrap(), tagged);
+        // lines without the note column have no note
+        let old: Purchase =
+            parse_line("scarf, 20, presents, brand, 1, 2024-01-02, shop, family", 1).unwrap();
+        assert_eq!(old.note, None);
+        assert_eq!(gift.with_note(" ").note, None);
+    }
+
+    #[test]
+    fn balance_accumulates_income_minus_spending_per_month() {
+        let purchases = [
+            purchase("rent", 80000, "other", 1, "2024-01-05"),
+            purchase("food", 20000, "food", 1, "2024-03-10"),
+        ];
+        let income = [
+            Income {
+                date: "2024-01-01".parse().unwrap(),
+                amount_cents: 150000,
+                source: String::from("salary"),
+            },
+            Income {
+                date: "2024-02-01".parse().unwrap(),
+                amount_cents: 150000,
+                source: String::new(),
+            },
+        ];
+        assert_eq!(
+            monthly_balance(&purchases, &income),
+            [
+                ((2024, 1), 150000, 80000, 70000),
+                ((2024, 2), 150000, 0, 220000),
+                ((2024, 3), 0, 20000, 200000),
+            ]
+        );
+        assert_eq!(income[1].to_line(), "2024-02-01, 1500.00");
+    }
+
+    #[test]
+    fn stats_machine_formats_match_the_text() {
+        let write = |format: OutputFormat| -> String {
+            let mut out: Vec<u8> = Vec::new();
+            write_stats(&[100, 250, 300], format, &Currency::default(), &mut out).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+        assert!(write(OutputFormat::Text).contains("mean: $2.17\n"));
+        assert_eq!(
+            write(OutputFormat::Json),
+            "{\"count\":3,\"total\":6.50,\"mean\":2.17,\"median\":2.50,\"min\":1.00,\
+             \"max\":3.00,\"std_dev\":0.85}\n"
+        );
+        assert_eq!(
+            write(OutputFormat::Csv),
+            "3,6.50,2.17,2.50,1.00,3.00,0.85\n"
+        );
+        let json: serde_json::Value = serde_json::from_str(&write(OutputFormat::Json)).unwrap();
+        assert_eq!(json["count"], 3);
+    }
+
+    #[test]
+    fn flags_prices_far_from_the_product_median() {
+        let purchases = [
+            purchase("coffee", 300, "food", 1, "2024-01-02"),
+            purchase("Coffee", 3000, "food", 1, "2024-01-03"),
+            purchase("coffee", 320, "food", 1, "2024-01-04"),
+            purchase("coffee", 90, "food", 1, "2024-01-05"),
+            // too few purchases to know the usual price
+            purchase("tea", 300, "food", 1, "2024-01-06"),
+            purchase("tea", 5000, "food", 1, "2024-01-07"),
+        ];
+        assert_eq!(
+            price_outliers(&purchases, DEFAULT_OUTLIER_FACTOR),
+            [(1, 310.0), (3, 310.0)]
+        );
+        assert!(price_outliers(&purchases, 10.0).is_empty());
+    }
+
+    #[test]
+    fn every_variant_round_trips_through_its_name() {
+        for category in ProductType::all_variants() {
+            assert!(category.built_in_name().is_some());
+            assert_eq!(ProductType::from_string(&category.to_string()), category);
+        }
+        let custom = ProductType::Custom(String::from("housing"));
+        assert_eq!(ProductType::from_string(&custom.to_string()), custom);
+    }
+
+    #[test]
+    fn quarters_follow_the_calendar() {
+        let purchases = [
+            purchase("tree", 3000, "other", 1, "2023-12-31"),
+            purchase("cake", 500, "food", 1, "2024-01-01"),
+            purchase("tea", 300, "food", 1, "2024-03-31"),
+            purchase("hat", 2000, "style", 1, "2024-04-01"),
+        ];
+        let totals: Vec<((i32, u8), i64)> = quarterly_totals(&purchases).into_iter().collect();
+        assert_eq!(
+            totals,
+            [((2023, 4), 3000), ((2024, 1), 800), ((2024, 2), 2000)]
+        );
+    }
+
+    #[test]
+    fn what_if_sums_the_excluded_buckets() {
+        let purchases = [
+            purchase("flight", 30000, "travel", 1, "2024-01-02"),
+            purchase("hat", 2000, "style", 2, "2024-01-03"),
+            purchase("bread", 300, "food", 1, "2024-01-04"),
+        ];
+        let excluded = [ProductType::Travel, ProductType::Style];
+        assert_eq!(what_if(&purchases, &excluded), (34300, 34000));
+        assert_eq!(what_if(&purchases, &[ProductType::Culture]), (34300, 0));
+    }
+
+    #[test]
+    fn parses_prices_with_symbols_and_thousands_separators() {
+        assert_eq!(parse_price("$1,299.00"), Some(129900));
+        assert_eq!(parse_price("1 299,00"), Some(129900));
+        assert_eq!(parse_price("99"), Some(9900));
+        assert_eq!(parse_price("12,50 €"), Some(1250));
+        assert_eq!(parse_price("1.299,99€"), Some(129999));
+        assert_eq!(parse_price("1,299"), Some(129900));
+        assert_eq!(parse_price("1.299"), Some(129900));
+        assert_eq!(parse_price("2.345,5"), Some(234550));
+        assert_eq!(parse_price("2.34"), Some(234));
+        assert_eq!(parse_price("92233720368547758.08"), None);
+        assert_eq!(parse_price(&"9".repeat(40)), None);
+        assert_eq!(parse_price("USD 5"), Some(500));
+        assert_eq!(parse_price("$"), None);
+        let line: &str = "laptop, \"$1,299.00\", technology, brand, 1, 2024-01-02, shop";
+        assert_eq!(parse_line(line, 1).unwrap().product.price_cents, 129900);
+    }
+
+    #[test]
+    fn archives_purchases_before_the_cutoff() {
+        let file = |name: &str| Store {
+            file_name: std::env::temp_dir()
+                .join(format!("fima-archive-{}-{}.txt", std::process::id(), name))
+                .to_string_lossy()
+                .into_owned(),
+            format: Format::Text,
+            merged: Vec::new(),
+        };
+        let (store, archive) = (file("data"), file("archive"));
+        let old: Purchase = purchase("old", 100, "food", 1, "2023-01-01");
+        let older: Purchase = purchase("older", 100, "food", 1, "2022-01-01");
+        let new: Purchase = purchase("new", 100, "food", 1, "2024-01-01");
+        store.write_all(&[old.clone(), new.clone()]).unwrap();
+        archive.write_all(std::slice::from_ref(&older)).unwrap();
+        let before: NaiveDate = "2024-01-01".parse().unwrap();
+        assert_eq!(archive_purchases(&store, &archive, before).unwrap(), (1, 1));
+        assert_eq!(store.read().unwrap(), [new]);
+        assert_eq!(archive.read().unwrap(), [older, old]);
+        assert_eq!(archive_purchases(&store, &archive, before).unwrap(), (0, 1));
+        std::fs::remove_file(&store.file_name).unwrap();
+        std::fs::remove_file(&archive.file_name).unwrap();
+    }
+
+    #[test]
+    fn formats_amounts_for_a_locale() {
+        let german: Currency = Currency::from_locale("de_DE.UTF-8").unwrap();
+        assert_eq!(format_amount(129900, &german), "1.299,00 €");
+        assert_eq!(format_amount(-123456789, &german), "-1.234.567,89 €");
+        assert_eq!(format_amount(99900, &german), "999,00 €");
+        let american: Currency = Currency::from_locale("en-US").unwrap();
+        assert_eq!(format_amount(129900, &american), "$1,299.00");
+        assert!(Currency::from_locale("xx-XX").is_none());
+        assert_eq!(format_amount(129900, &Currency::default()), "$1299.00");
+    }
+
+    #[test]
+    fn summarizes_the_added_purchases_per_category() {
+        let purchases = [
+            purchase("bread", 300, "food", 1, "2024-01-02"),
+            purchase("book", 1500, "education", 1, "2024-01-02"),
+            purchase("milk", 100, "food", 2, "2024-01-02"),
+        ];
+        let mut out: Vec<u8> = Vec::new();
+        write_added_summary(&purchases, &Currency::default(), &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "Added 3 purchases worth $20.00\n  education: $15.00 (1)\n  food: $5.00 (2)\n"
+        );
+    }
+
+    #[test]
+    fn projects_the_year_from_the_days_elapsed() {
+        let purchases = [
+            purchase("old", 10000, "food", 1, "2023-12-31"),
+            purchase("bread", 300, "food", 1, "2024-01-01"),
+            purchase("flight", 20000, "travel", 1, "2024-01-10"),
+            purchase("later", 500, "food", 1, "2024-01-11"),
+        ];
+        let today: NaiveDate = "2024-01-10".parse().unwrap();
+        let (projections, elapsed, days) = year_projection(&purchases, today);
+        assert_eq!((elapsed, days), (10, 366));
+        assert_eq!(
+            projections,
+            [
+                (ProductType::Travel, 20000, 732000),
+                (ProductType::Food, 300, 10980)
+            ]
+        );
+        // the first day of the year is projected from that day alone
+        let (projections, _, _) = year_projection(&purchases, "2024-01-01".parse().unwrap());
+        assert_eq!(projections, [(ProductType::Food, 300, 109800)]);
+    }
+
+    #[test]
+    fn converts_prices_in_other_currencies_only_when_reporting() {
+        let line: &str = "croissant, 2.50 EUR, food, brand, 2, 2024-01-02, shop,";
+        let croissant: Purchase = parse_line(line, 1).unwrap();
+        assert_eq!(croissant.currency.as_deref(), Some("EUR"));
+        assert_eq!(croissant.product.price_cents, 250);
+        assert_eq!(purchase_to_line(&croissant), line);
+        let mut purchases = [
+            croissant,
+            purchase("bread", 300, "food", 1, "2024-01-02"),
+            purchase("tea", 1000, "food", 1, "2024-01-02").with_currency(Some(String::from("GBP"))),
+        ];
+        let rates: HashMap<String, i64> = HashMap::from([(String::from("EUR"), 1_085_000)]);
+        assert_eq!(convert_currencies(&mut purchases, &rates), ["GBP"]);
+        // 2.50 EUR at 1.085 is 2.7125
+        assert_eq!(purchases[0].product.price_cents, 271);
+        assert_eq!(purchases[0].currency, None);
+        assert_eq!(purchases[1].product.price_cents, 300);
+        assert_eq!(purchases[2].product.price_cents, 1000);
+    }
+
+    #[test]
+    fn check_reports_every_problem_with_its_line() {
+        let contents: &str = "apple, 1.00, food, brand, 1, 2024-01-02, shop\n\
+            pear, -1.00, food, brand, 1, 2024-01-03, shop\n\
+            \n\
+            plum, 1.00, food, brand, 1, 2024-13-01, shop\n\
+            cake, 1.00, food\n\
+            apple, 1.00, food, brand, 1, 2024-01-02, shop\n\
+            tea, 1.00, food, brand, 1, 2024-02-01, shop\n";
+        let today: NaiveDate = "2024-01-31".parse().unwrap();
+        let problems: Vec<String> = check_lines(contents.as_bytes(), today).unwrap();
+        assert_eq!(
+            problems,
+            [
+                "line 2: price -1.00 must not be negative",
+                "line 4: date '2024-13-01' cannot be parsed",
+                "line 5: missing field",
+                "line 6: duplicates line 1",
+                "line 7: date 2024-02-01 is in the future",
+            ]
+        );
+        assert!(check_lines(&contents.as_bytes()[..46], today)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn report_cache_is_reused_only_while_the_file_is_unchanged() {
+        let path = std::env::temp_dir().join(format!("fima-cache-{}.txt", std::process::id()));
+        let store = Store {
+            file_name: path.to_string_lossy().into_owned(),
+            format: Format::Text,
+            merged: Vec::new(),
+        };
+        let cache_file: String = format!("{}.cache", store.file_name);
+        let options = ReportOptions {
+            from: None,
+            to: None,
+            top: None,
+            units: true,
+            chart: None,
+            adjust_to: None,
+            json: false,
+            expand_other: DEFAULT_EXPAND_OTHER,
+        };
+        let report = |cache: bool| -> String {
+            let mut out: Vec<u8> = Vec::new();
+            exec_bucket_comparison(&store, &options, cache, &Currency::default(), &mut out)
+                .unwrap();
+            String::from_utf8(out).unwrap()
+        };
+        store
+            .write_all(&[
+                purchase("stamps", 40, "other", 3, "2024-01-02"),
+                purchase("bread", 300, "food", 1, "2024-01-03"),
+            ])
+            .unwrap();
+        let fresh: String = report(false);
+        assert!(!std::path::Path::new(&cache_file).exists());
+        assert_eq!(report(true), fresh);
+        assert!(std::path::Path::new(&cache_file).exists());
+        assert_eq!(report(true), fresh);
+        // a changed file is never answered from the stale cache
+        store
+            .append(&purchase("tea", 500, "food", 1, "2024-01-04"))
+            .unwrap();
+        assert_eq!(report(true), report(false));
+        assert_ne!(report(true), fresh);
+        // the warnings of reading the purchases are kept to be repeated
+        store
+            .append(&purchase("later", 100, "food", 1, "2999-01-01"))
+            .unwrap();
+        let mut file: File = OpenOptions::new()
+            .append(true)
+            .open(&store.file_name)
+            .unwrap();
+        file.write_all(b"\nbroken, 1.00").unwrap();
+        report(true);
+        let key: u64 = cache_key(&std::fs::read(&store.file_name).unwrap());
+        let cache: ReportCache = read_cache(&cache_file, key).unwrap();
+        let date: NaiveDate = "2999-01-01".parse().unwrap();
+        assert!(cache.future.contains(&(
+            String::from("later, 1.00, food, brand, 1, 2999-01-01, shop,"),
+            date
+        )));
+        assert!(cache
+            .warnings
+            .iter()
+            .any(|warning| warning.starts_with("skipping line 5")));
+        std::fs::remove_file(&store.file_name).unwrap();
+        std::fs::remove_file(&cache_file).unwrap();
+    }
+
+    #[test]
+    fn velocity_compares_this_month_with_the_complete_months_before() {
+        let purchases = [
+            purchase("rent", 3100, "other", 1, "2024-01-15"),
+            // February has no purchases and counts as zero
+            purchase("food", 600, "food", 1, "2024-03-02"),
+            purchase("later", 500, "food", 1, "2024-03-20"),
+        ];
+        let today: NaiveDate = "2024-03-03".parse().unwrap();
+        // 3100 over the 60 days of January and February, 600 over 3 days of March
+        assert_eq!(
+            spending_velocity(&purchases, today),
+            Some((200.0, 3100.0 / 60.0))
+        );
+        assert_eq!(spending_velocity(&purchases[1..], today), None);
+    }
+
+    #[test]
+    fn day_totals_fill_dates_without_purchases() {
+        let purchases = [
+            purchase("apple", 100, "food", 1, "2024-01-03"),
+            purchase("pear", 200, "food", 2, "2024-01-01"),
+            purchase("plum", 300, "food", 1, "2024-01-03"),
+        ];
+        let date = |date: &str| date.parse::<NaiveDate>().unwrap();
+        assert_eq!(
+            day_totals(&purchases),
+            [
+                (date("2024-01-01"), 400),
+                (date("2024-01-02"), 0),
+                (date("2024-01-03"), 400)
+            ]
+        );
+        assert_eq!(day_totals(&[]), []);
+    }
+
+    #[test]
+    fn failed_rewrite_keeps_the_original_file() {
+        let path = std::env::temp_dir().join(format!("fima-replace-{}.txt", std::process::id()));
+        let file_name: &str = path.to_str().unwrap();
+        std::fs::write(file_name, "old contents").unwrap();
+        let result = replace_file(file_name, |file| {
+            file.write_all(b"partial")?;
+            Err(std::io::Error::other("interrupted"))
+        });
+        assert!(result.is_err());
+        assert_eq!(std::fs::read_to_string(file_name).unwrap(), "old contents");
+        assert!(!std::path::Path::new(&format!("{}.tmp", file_name)).exists());
+        replace_file(file_name, |file| file.write_all(b"new contents")).unwrap();
+        assert_eq!(std::fs::read_to_string(file_name).unwrap(), "new contents");
+        std::fs::remove_file(file_name).unwrap();
+    }
+
+    #[test]
+    fn goal_progress_tracks_and_settles_goals() {
+        let purchases = [
+            purchase("apple", 1000, "food", 1, "2024-03-01"),
+            purchase("pear", 500, "food", 1, "2024-03-02"),
+            purchase("later", 9000, "food", 1, "2024-04-01"),
+        ];
+        let goal = Goal {
+            name: String::from("march"),
+            from: "2024-03-01".parse().unwrap(),
+            to: "2024-03-10".parse().unwrap(),
+            cap_cents: 5000,
+        };
+        let date = |date: &str| date.parse::<NaiveDate>().unwrap();
+        assert_eq!(
+            goal_progress(&goal, &purchases, date("2024-02-29")),
+            (0, GoalStatus::Upcoming)
+        );
+        // 15.00 in 2 of 10 days projects to 75.00
+        assert_eq!(
+            goal_progress(&goal, &purchases, date("2024-03-02")),
+            (1500, GoalStatus::OffTrack)
+        );
+        assert_eq!(
+            goal_progress(&goal, &purchases, date("2024-03-05")),
+            (1500, GoalStatus::OnTrack)
+        );
+        assert_eq!(
+            goal_progress(&goal, &purchases, date("2024-04-02")),
+            (1500, GoalStatus::Met)
+        );
+        let tight = Goal {
+            cap_cents: 1000,
+            ..goal.clone()
+        };
+        assert_eq!(
+            goal_progress(&tight, &purchases, date("2024-04-02")),
+            (1500, GoalStatus::Missed)
+        );
+        assert_eq!(
+            Goal {
+                name: String::from("a, b"),
+                ..goal
+            }
+            .to_line(),
+            "\"a, b\", 2024-03-01, 2024-03-10, 50.00"
+        );
+    }
+
+    #[test]
+    fn undo_keeps_purchases_appended_after_the_undone_one() {
+        let path = std::env::temp_dir().join(format!("fima-undo-{}.txt", std::process::id()));
+        let store = Store {
+            file_name: path.to_string_lossy().into_owned(),
+            format: Format::Text,
+            merged: Vec::new(),
+        };
+        let _ = std::fs::remove_file(&path);
+        let (apple, pear, plum) = (
+            purchase("apple", 100, "food", 1, "2024-01-02"),
+            purchase("pear", 200, "food", 1, "2024-01-02"),
+            purchase("plum", 300, "food", 1, "2024-01-02"),
+        );
+        store.append(&apple).unwrap();
+        let len: u64 = store.append(&pear).unwrap();
+        store.undo_append(len, &pear).unwrap();
+        assert_eq!(store.read().unwrap(), std::slice::from_ref(&apple));
+        // another process appended after the purchase, so undoing it is refused
+        let len: u64 = store.append(&pear).unwrap();
+        store.append(&plum).unwrap();
+        assert!(store.undo_append(len, &pear).is_err());
+        assert_eq!(store.read().unwrap(), [apple, pear, plum]);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn archives_into_a_new_file() {
+        let file = |name: &str| Store {
+            file_name: std::env::temp_dir()
+                .join(format!(
+                    "fima-new-archive-{}-{}.txt",
+                    std::process::id(),
+                    name
+                ))
+                .to_string_lossy()
+                .into_owned(),
+            format: Format::Text,
+            merged: Vec::new(),
+        };
+        let (store, archive) = (file("data"), file("archive"));
+        let _ = std::fs::remove_file(&archive.file_name);
+        let old: Purchase = purchase("old", 100, "food", 1, "2023-01-01");
+        let new: Purchase = purchase("new", 100, "food", 1, "2024-01-01");
+        store.write_all(&[old.clone(), new.clone()]).unwrap();
+        let before: NaiveDate = "2024-01-01".parse().unwrap();
+        assert_eq!(archive_purchases(&store, &archive, before).unwrap(), (1, 1));
+        assert_eq!(archive.read().unwrap(), [old]);
+        assert_eq!(store.read().unwrap(), [new]);
+        std::fs::remove_file(&store.file_name).unwrap();
+        std::fs::remove_file(&archive.file_name).unwrap();
+    }
+
+    #[test]
+    fn converts_a_single_purchase() {
+        let rates: HashMap<String, i64> = HashMap::from([(String::from("EUR"), 1_100_000)]);
+        let mut euro: Purchase =
+            purchase("tea", 1000, "food", 1, "2024-01-02").with_currency(Some(String::from("EUR")));
+        assert_eq!(convert_currency(&mut euro, &rates), None);
+        assert_eq!((euro.product.price_cents, euro.currency), (1100, None));
+        let mut pound: Purchase =
+            purchase("tea", 1000, "food", 1, "2024-01-02").with_currency(Some(String::from("GBP")));
+        assert_eq!(
+            convert_currency(&mut pound, &rates),
+            Some(String::from("GBP"))
+        );
+        assert_eq!(pound.product.price_cents, 1000);
+    }
+}
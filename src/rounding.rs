@@ -0,0 +1,74 @@
+//! Rounding policy for displayed amounts and percentages
+//! Amounts are rounded to whole cents and percentages to one decimal, both half to even
+
+pub fn round_cents(cents: f64) -> i64 {
+    //! Round a computed amount (like a mean) to whole cents
+    cents.round_ties_even() as i64
+}
+
+pub fn round_div(value: i64, divisor: i64) -> i64 {
+    //! Divide by a positive divisor, rounding the quotient half to even
+    let quotient: i64 = value.div_euclid(divisor);
+    let remainder: i64 = value.rem_euclid(divisor);
+    match (2 * remainder).cmp(&divisor) {
+        std::cmp::Ordering::Less => quotient,
+        std::cmp::Ordering::Greater => quotient + 1,
+        std::cmp::Ordering::Equal => quotient + quotient.rem_euclid(2),
+    }
+}
+
+pub fn percentages(values: &[i64]) -> Vec<f64> {
+    //! Share of each value in their sum, in percent with one decimal
+    //! Shares are rounded by the largest remainder method, so they add up to exactly 100.0;
+    //! ties go to the earlier value
+    //! All shares are 0 if the sum is 0
+    let total: i128 = values.iter().map(|&value| value as i128).sum();
+    if total == 0 {
+        return vec![0.0; values.len()];
+    }
+    // shares in tenths of a percent, rounded down, with what was rounded off
+    let mut tenths: Vec<(i128, i128)> = values
+        .iter()
+        .map(|&value| {
+            let scaled: i128 = value as i128 * 1000;
+            (scaled.div_euclid(total), scaled.rem_euclid(total))
+        })
+        .collect();
+    let missing: i128 = 1000 - tenths.iter().map(|(share, _)| share).sum::<i128>();
+    let mut order: Vec<usize> = (0..values.len()).collect();
+    order.sort_by_key(|&index| std::cmp::Reverse(tenths[index].1));
+    for &index in order.iter().take(missing.max(0) as usize) {
+        tenths[index].0 += 1;
+    }
+    tenths
+        .into_iter()
+        .map(|(share, _)| share as f64 / 10.0)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rounds_half_to_even() {
+        assert_eq!(round_cents(2.5), 2);
+        assert_eq!(round_cents(3.5), 4);
+        assert_eq!(round_div(250, 100), 2);
+        assert_eq!(round_div(350, 100), 4);
+        assert_eq!(round_div(-250, 100), -2);
+        assert_eq!(round_div(251, 100), 3);
+    }
+
+    #[test]
+    fn percentages_add_up_to_100() {
+        let shares: Vec<f64> = percentages(&[1, 1, 1]);
+        assert_eq!(shares, [33.4, 33.3, 33.3]);
+        let tenths: i64 = percentages(&[205, 333, 462, 1])
+            .iter()
+            .map(|share| (share * 10.0).round() as i64)
+            .sum();
+        assert_eq!(tenths, 1000);
+        assert_eq!(percentages(&[0, 0]), [0.0, 0.0]);
+    }
+}
@@ -0,0 +1,184 @@
+//! SQLite store for purchases, an alternative to the flat data file
+//! Purchases are kept in insertion order by their row id
+
+use crate::{Product, ProductType, Purchase, Quantity};
+use chrono::NaiveDate;
+use rusqlite::types::Type;
+use rusqlite::{params, Connection};
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS purchases (
+    id INTEGER PRIMARY KEY,
+    name TEXT NOT NULL,
+    price_cents INTEGER NOT NULL,
+    product_type TEXT NOT NULL,
+    brand TEXT NOT NULL,
+    quantity INTEGER NOT NULL,
+    date TEXT NOT NULL,
+    shop TEXT NOT NULL,
+    tags TEXT NOT NULL,
+    note TEXT,
+    currency TEXT
+);
+CREATE INDEX IF NOT EXISTS purchases_date ON purchases (date);";
+
+pub fn open(file_name: &str) -> rusqlite::Result<Connection> {
+    //! Open the database, creating it and its tables if they do not exist yet
+    //! Databases created before notes or currencies existed get those columns added
+    let connection: Connection = Connection::open(file_name)?;
+    connection.execute_batch(SCHEMA)?;
+    for column in ["note", "currency"] {
+        let exists: bool = connection
+            .prepare("SELECT 1 FROM pragma_table_info('purchases') WHERE name = ?1")?
+            .exists([column])?;
+        if !exists {
+            connection.execute(
+                &format!("ALTER TABLE purchases ADD COLUMN {} TEXT", column),
+                [],
+            )?;
+        }
+    }
+    Ok(connection)
+}
+
+fn insert(connection: &Connection, purchase: &Purchase) -> rusqlite::Result<()> {
+    //! Insert a purchase as a new row
+    //! The quantity is stored as a number, negative for refunds like in the data file;
+    //! the integer column keeps whole quantities as integers
+    let quantity: f64 = if purchase.refund {
+        -purchase.quantity.as_f64()
+    } else {
+        purchase.quantity.as_f64()
+    };
+    connection.execute(
+        "INSERT INTO purchases (name, price_cents, product_type, brand, quantity, date, shop, tags, note, currency)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+        params![
+            purchase.product.name,
+            purchase.product.price_cents,
+            purchase.product.product_type.to_string(),
+            purchase.product.brand,
+            quantity,
+            purchase.date,
+            purchase.shop,
+            purchase.tags.join(" "),
+            purchase.note,
+            purchase.currency,
+        ],
+    )?;
+    Ok(())
+}
+
+pub fn append(connection: &Connection, purchase: &Purchase) -> rusqlite::Result<()> {
+    //! Add a purchase after all others
+    insert(connection, purchase)
+}
+
+pub fn read(
+    connection: &Connection,
+    from: Option<NaiveDate>,
+    to: Option<NaiveDate>,
+) -> rusqlite::Result<Vec<Purchase>> {
+    //! Read the purchases dated within the inclusive range (unbounded where None)
+    //! in the order they were added
+    let mut statement = connection.prepare(
+        "SELECT name, price_cents, product_type, brand, quantity, date, shop, tags, note, currency
+         FROM purchases
+         WHERE (?1 IS NULL OR date >= ?1) AND (?2 IS NULL OR date <= ?2)
+         ORDER BY id",
+    )?;
+    let rows = statement.query_map(params![from, to], |row| {
+        let product_type: String = row.get(2)?;
+        let tags: String = row.get(7)?;
+        let quantity: f64 = row.get(4)?;
+        let invalid =
+            || rusqlite::Error::FromSqlConversionFailure(4, Type::Real, "invalid quantity".into());
+        Ok(Purchase {
+            product: Product {
+                name: row.get(0)?,
+                price_cents: row.get(1)?,
+                product_type: ProductType::from_string(&product_type),
+                brand: row.get(3)?,
+            },
+            quantity: Quantity::from_f64(quantity.abs()).ok_or_else(invalid)?,
+            date: row.get(5)?,
+            shop: row.get(6)?,
+            tags: tags.split_whitespace().map(String::from).collect(),
+            refund: quantity < 0.0,
+            note: row.get(8)?,
+            currency: row.get(9)?,
+        })
+    })?;
+    rows.collect()
+}
+
+pub fn count(connection: &Connection) -> rusqlite::Result<u64> {
+    //! Number of stored purchases
+    let count: i64 =
+        connection.query_row("SELECT COUNT(*) FROM purchases", [], |row| row.get(0))?;
+    Ok(count as u64)
+}
+
+pub fn truncate(connection: &Connection, count: u64) -> rusqlite::Result<()> {
+    //! Keep only the first count purchases that were added
+    connection.execute(
+        "DELETE FROM purchases WHERE id NOT IN (SELECT id FROM purchases ORDER BY id LIMIT ?1)",
+        params![count as i64],
+    )?;
+    Ok(())
+}
+
+pub fn replace_all(connection: &mut Connection, purchases: &[Purchase]) -> rusqlite::Result<()> {
+    //! Replace all stored purchases with the given ones in a single transaction
+    let transaction = connection.transaction()?;
+    transaction.execute("DELETE FROM purchases", [])?;
+    for purchase in purchases {
+        insert(&transaction, purchase)?;
+    }
+    transaction.commit()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn purchase(name: &str, price_cents: i64, date: &str) -> Purchase {
+        let product = Product::new(
+            name.to_string(),
+            price_cents,
+            ProductType::Food,
+            String::from("brand"),
+        )
+        .unwrap();
+        Purchase::new(
+            product,
+            Quantity::parse("1.25").unwrap(),
+            date.parse().unwrap(),
+            String::from("shop"),
+        )
+        .unwrap()
+        .with_tags(&["weekly"])
+    }
+
+    #[test]
+    fn round_trips_and_filters_by_date() {
+        let mut connection: Connection = open(":memory:").unwrap();
+        let purchases = [
+            purchase("Apple", 150, "2024-01-02"),
+            purchase("pear", 99, "2024-02-03").with_refund(true),
+            purchase("plum", 30, "2024-03-04")
+                .with_note("for jam")
+                .with_currency(Some(String::from("EUR"))),
+        ];
+        replace_all(&mut connection, &purchases[..2]).unwrap();
+        append(&connection, &purchases[2]).unwrap();
+        assert_eq!(read(&connection, None, None).unwrap(), purchases);
+        let from: Option<NaiveDate> = "2024-02-03".parse().ok();
+        assert_eq!(
+            read(&connection, from, None).unwrap(),
+            purchases[1..].to_vec()
+        );
+        truncate(&connection, 1).unwrap();
+        assert_eq!(count(&connection).unwrap(), 1);
+    }
+}
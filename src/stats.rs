@@ -0,0 +1,62 @@
+//! Summary statistics over purchase values (in cents)
+
+pub fn mean(values: &[i64]) -> Option<f64> {
+    //! Arithmetic mean of the values, None if there are none
+    if values.is_empty() {
+        return None;
+    }
+    Some(values.iter().sum::<i64>() as f64 / values.len() as f64)
+}
+
+pub fn median(values: &[i64]) -> Option<f64> {
+    //! Median of the values, None if there are none
+    //! For an even number of values this is the mean of the two middle ones
+    if values.is_empty() {
+        return None;
+    }
+    let mut sorted: Vec<i64> = values.to_vec();
+    sorted.sort_unstable();
+    let middle: usize = sorted.len() / 2;
+    if sorted.len().is_multiple_of(2) {
+        Some((sorted[middle - 1] + sorted[middle]) as f64 / 2.0)
+    } else {
+        Some(sorted[middle] as f64)
+    }
+}
+
+pub fn std_dev(values: &[i64]) -> Option<f64> {
+    //! Population standard deviation of the values, None if there are none
+    let mean: f64 = mean(values)?;
+    let variance: f64 = values
+        .iter()
+        .map(|value| (*value as f64 - mean).powi(2))
+        .sum::<f64>()
+        / values.len() as f64;
+    Some(variance.sqrt())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn median_of_odd_and_even_length() {
+        assert_eq!(median(&[300, 100, 200]), Some(200.0));
+        assert_eq!(median(&[400, 100, 300, 200]), Some(250.0));
+    }
+
+    #[test]
+    fn empty_values_have_no_statistics() {
+        assert_eq!(mean(&[]), None);
+        assert_eq!(median(&[]), None);
+        assert_eq!(std_dev(&[]), None);
+    }
+
+    #[test]
+    fn std_dev_is_population_deviation() {
+        assert_eq!(
+            std_dev(&[200, 400, 400, 400, 500, 500, 700, 900]),
+            Some(200.0)
+        );
+    }
+}
@@ -0,0 +1,177 @@
+//! Interactive browser for purchases, only with the tui feature
+//! Read-only: a scrollable table that can be filtered by category and sorted,
+//! with the total of the rows shown at the bottom
+
+use crate::{format_amount, Currency, ProductType, Purchase, SortKey};
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Modifier, Style};
+use ratatui::widgets::{Paragraph, Row, Table, TableState};
+use ratatui::{DefaultTerminal, Frame};
+
+// sort keys in the order the s key cycles through them
+const SORT_KEYS: &[(SortKey, &str)] = &[
+    (SortKey::Date, "date"),
+    (SortKey::Price, "price"),
+    (SortKey::Name, "name"),
+    (SortKey::Value, "value"),
+];
+
+struct Browser<'a> {
+    purchases: &'a [Purchase],
+    currency: &'a Currency,
+    // categories of the purchases, in order of first appearance
+    categories: Vec<ProductType>,
+    // index into categories of the category shown, all are shown if None
+    category: Option<usize>,
+    // index into SORT_KEYS
+    sort: usize,
+    reverse: bool,
+    visible: Vec<&'a Purchase>,
+    state: TableState,
+}
+
+impl<'a> Browser<'a> {
+    fn new(purchases: &'a [Purchase], currency: &'a Currency) -> Browser<'a> {
+        let mut categories: Vec<ProductType> = Vec::new();
+        for purchase in purchases {
+            if !categories.contains(&purchase.product.product_type) {
+                categories.push(purchase.product.product_type.clone());
+            }
+        }
+        let mut browser = Browser {
+            purchases,
+            currency,
+            categories,
+            category: None,
+            sort: 0,
+            reverse: false,
+            visible: Vec::new(),
+            state: TableState::default(),
+        };
+        browser.update();
+        browser
+    }
+
+    fn update(&mut self) {
+        //! Recompute the visible rows after the filter or the sort order changed
+        //! The first row is selected again
+        let category: Option<&ProductType> = self.category.map(|index| &self.categories[index]);
+        self.visible = self
+            .purchases
+            .iter()
+            .filter(|purchase| {
+                category.is_none_or(|category| purchase.product.product_type == *category)
+            })
+            .collect();
+        let (sort, _) = SORT_KEYS[self.sort];
+        // sort is stable, so ties keep file order even when reversed
+        self.visible.sort_by(|a, b| {
+            let ordering = sort.compare(a, b);
+            if self.reverse {
+                ordering.reverse()
+            } else {
+                ordering
+            }
+        });
+        self.state.select((!self.visible.is_empty()).then_some(0));
+    }
+
+    fn handle_key(&mut self, key: KeyCode) -> bool {
+        //! React to a key press, returning false once the browser should close
+        match key {
+            KeyCode::Char('q') | KeyCode::Esc => return false,
+            KeyCode::Down | KeyCode::Char('j') => self.state.select_next(),
+            KeyCode::Up | KeyCode::Char('k') => self.state.select_previous(),
+            KeyCode::PageDown => self.state.scroll_down_by(20),
+            KeyCode::PageUp => self.state.scroll_up_by(20),
+            KeyCode::Home => self.state.select_first(),
+            KeyCode::End => self.state.select_last(),
+            KeyCode::Char('c') => {
+                // cycle through the categories, then back to all
+                self.category = match self.category {
+                    None if !self.categories.is_empty() => Some(0),
+                    Some(index) if index + 1 < self.categories.len() => Some(index + 1),
+                    _ => None,
+                };
+                self.update();
+            }
+            KeyCode::Char('s') => {
+                self.sort = (self.sort + 1) % SORT_KEYS.len();
+                self.update();
+            }
+            KeyCode::Char('r') => {
+                self.reverse = !self.reverse;
+                self.update();
+            }
+            _ => {}
+        }
+        true
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        //! Draw the table of visible purchases above a status line
+        let [table_area, status_area] =
+            Layout::vertical([Constraint::Min(1), Constraint::Length(1)]).areas(frame.area());
+        let rows: Vec<Row> = self
+            .visible
+            .iter()
+            .map(|purchase| {
+                Row::new([
+                    purchase.date.to_string(),
+                    purchase.product.name.clone(),
+                    purchase.product.product_type.to_string(),
+                    purchase.signed_quantity(),
+                    format_amount(purchase.product.price_cents, self.currency),
+                    format_amount(purchase.value(), self.currency),
+                ])
+            })
+            .collect();
+        let header = Row::new(["date", "name", "type", "quantity", "price", "value"])
+            .style(Style::new().add_modifier(Modifier::BOLD));
+        let widths = [
+            Constraint::Length(10),
+            Constraint::Fill(1),
+            Constraint::Length(14),
+            Constraint::Length(8),
+            Constraint::Length(12),
+            Constraint::Length(12),
+        ];
+        let table = Table::new(rows, widths)
+            .header(header)
+            .row_highlight_style(Style::new().add_modifier(Modifier::REVERSED));
+        frame.render_stateful_widget(table, table_area, &mut self.state);
+        let total: i64 = self.visible.iter().map(|purchase| purchase.value()).sum();
+        let category: String = match self.category {
+            Some(index) => self.categories[index].to_string(),
+            None => String::from("all"),
+        };
+        let status: String = format!(
+            "{} purchases, total {} | category: {} (c) | sort: {}{} (s, r) | q to quit",
+            self.visible.len(),
+            format_amount(total, self.currency),
+            category,
+            SORT_KEYS[self.sort].1,
+            if self.reverse { " reversed" } else { "" }
+        );
+        frame.render_widget(Paragraph::new(status), status_area);
+    }
+
+    fn run(&mut self, terminal: &mut DefaultTerminal) -> std::io::Result<()> {
+        loop {
+            terminal.draw(|frame| self.draw(frame))?;
+            if let Event::Key(key) = event::read()? {
+                if key.kind == KeyEventKind::Press && !self.handle_key(key.code) {
+                    return Ok(());
+                }
+            }
+        }
+    }
+}
+
+pub fn browse(purchases: &[Purchase], currency: &Currency) -> std::io::Result<()> {
+    //! Browse the purchases until the user quits
+    //! The terminal is restored afterwards, also on errors
+    let mut browser = Browser::new(purchases, currency);
+    ratatui::run(|terminal| browser.run(terminal))
+}